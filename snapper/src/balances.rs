@@ -0,0 +1,90 @@
+use serde::{ Serialize, Deserialize };
+use anyhow::Result;
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use crate::Account;
+use crate::holdings::{ Holding, HoldingSource };
+
+/// Holdings of a single address, split by where the funds sit on chain.
+///
+/// The total is `free + reserved + staked_out` plus everything `held`:
+/// - `frozen` is a lock on part of `free`, not an extra amount, so it is
+///   reported but never added to the total.
+/// - `staked_out` is the stake this address placed on modules (its `StakeTo`
+///   entries). Staking moves funds out of `free`, so they still belong to it.
+/// - `staked_in` is the stake placed on this address by anyone, including
+///   itself. The funds belong to the stakers and are only reported here.
+/// - `held` is what the snapshot's holding sources credit to this address,
+///   such as open proposal deposits, by source.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct Balance {
+    pub free: u128,
+    pub reserved: u128,
+    pub frozen: u128,
+    pub staked_out: u128,
+    pub staked_in: u128,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub held: BTreeMap<HoldingSource, u128>,
+    pub total: u128,
+}
+
+impl Balance {
+    /// Recomputes `total` from the other fields.
+    pub fn update_total(&mut self) {
+        self.total = self.free + self.reserved + self.staked_out + self.held.values().sum::<u128>();
+    }
+
+    /// Whether the address holds anything. Stake targets without an account
+    /// of their own get a record for their `staked_in` but hold nothing.
+    pub fn holds_funds(&self) -> bool {
+        self.total > 0
+    }
+}
+
+/// Aggregates accounts, stake edges and holdings into one balance record per
+/// address.
+pub fn map_balances(
+    accounts: &[(String, Account)],
+    stake: &[(String, String, u128)],
+    holdings: &[Holding]
+) -> BTreeMap<String, Balance> {
+    let mut balances: BTreeMap<String, Balance> = BTreeMap::new();
+
+    for (from, to, staked) in stake {
+        balances.entry(from.clone()).or_default().staked_out += staked;
+        balances.entry(to.clone()).or_default().staked_in += staked;
+    }
+    println!("{} final stake entries compared to {}", balances.len(), stake.len());
+
+    for (address, account) in accounts {
+        let balance = balances.entry(address.clone()).or_default();
+        balance.free += account.data.free;
+        balance.reserved += account.data.reserved;
+        balance.frozen += account.data.frozen;
+    }
+    println!("{} final balance entries compared to {}", balances.len(), accounts.len());
+
+    for holding in holdings {
+        let balance = balances.entry(holding.address.clone()).or_default();
+        *balance.held.entry(holding.source).or_default() += holding.amount;
+    }
+
+    for balance in balances.values_mut() {
+        balance.update_total();
+    }
+
+    balances
+}
+
+pub async fn save_balances(dir: &Path, balances: &BTreeMap<String, Balance>) -> Result<()> {
+    let json = serde_json::to_string_pretty(balances)?;
+    tokio::fs::write(dir.join("total_balances.json"), json).await?;
+
+    Ok(())
+}
+
+pub async fn load_balances(dir: &Path) -> Result<BTreeMap<String, Balance>> {
+    let json = tokio::fs::read_to_string(dir.join("total_balances.json")).await?;
+    Ok(serde_json::from_str(&json)?)
+}
@@ -0,0 +1,128 @@
+use serde::{ Serialize, Deserialize, de::DeserializeOwned };
+use anyhow::Result;
+use std::collections::HashMap;
+use std::path::{ Path, PathBuf };
+use tokio::io::AsyncWriteExt;
+
+use crate::SnapshotBlock;
+
+pub const CHECKPOINT_FILE: &str = "snapper.checkpoint.json";
+
+/// Progress of an interrupted `snap`, saved after every page so that
+/// `snap --resume` can continue where the previous run stopped.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Checkpoint {
+    /// Snapshot directory the checkpoint and the partial files live in.
+    #[serde(skip)]
+    pub dir: PathBuf,
+    pub block: SnapshotBlock,
+    /// State root of the block, set when read proofs are collected and
+    /// checked against it.
+    #[serde(default)]
+    pub state_root: Option<String>,
+    /// Progress per storage map, keyed by `Pallet.Entry`, with one cursor per
+    /// key range the map was split into.
+    pub maps: HashMap<String, Vec<MapCursor>>,
+}
+
+/// Progress through one range of a map's keys, all keys after the range's
+/// start up to and including `end`.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct MapCursor {
+    /// Hex encoded storage key the crawl continues after: the last key whose
+    /// entry has been written to disk, or the start of the range.
+    pub last_key: Option<String>,
+    /// Hex encoded last key of the range, `None` for the end of the map.
+    #[serde(default)]
+    pub end: Option<String>,
+    /// Entries written to the partial file so far.
+    pub entries: u64,
+    /// Entries that failed to decode and were written to the errors file.
+    #[serde(default)]
+    pub failed: u64,
+    /// Pages whose read proof was written to the proofs file.
+    #[serde(default)]
+    pub proofs: u64,
+    pub done: bool,
+}
+
+impl Checkpoint {
+    pub fn new(dir: &Path, block: SnapshotBlock) -> Self {
+        Self { dir: dir.to_path_buf(), block, state_root: None, maps: HashMap::new() }
+    }
+
+    pub async fn load(dir: &Path) -> Result<Option<Self>> {
+        match tokio::fs::read_to_string(dir.join(CHECKPOINT_FILE)).await {
+            Ok(json) => {
+                let checkpoint: Self = serde_json::from_str(&json)?;
+                Ok(Some(Self { dir: dir.to_path_buf(), ..checkpoint }))
+            }
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    /// Writes the checkpoint through a temporary file so that a crash never
+    /// leaves a half written checkpoint behind.
+    pub async fn save(&self) -> Result<()> {
+        let tmp = self.dir.join(format!("{}.tmp", CHECKPOINT_FILE));
+        tokio::fs::write(&tmp, serde_json::to_string_pretty(self)?).await?;
+        tokio::fs::rename(&tmp, self.dir.join(CHECKPOINT_FILE)).await?;
+
+        Ok(())
+    }
+
+    pub async fn remove(dir: &Path) -> Result<()> {
+        match tokio::fs::remove_file(dir.join(CHECKPOINT_FILE)).await {
+            Err(err) if err.kind() != std::io::ErrorKind::NotFound => Err(err.into()),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Truncates a partial JSON lines file to the entries covered by the
+/// checkpoint, dropping anything appended after the last saved cursor.
+pub async fn restore_partial(path: &Path, entries: u64) -> Result<()> {
+    let data = match tokio::fs::read_to_string(path).await {
+        Ok(data) => data,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => String::new(),
+        Err(err) => {
+            return Err(err.into());
+        }
+    };
+    let kept = data
+        .lines()
+        .take(entries as usize)
+        .map(|line| format!("{}\n", line))
+        .collect::<String>();
+    tokio::fs::write(path, kept).await?;
+
+    Ok(())
+}
+
+pub async fn append_partial(path: &Path, lines: &str) -> Result<()> {
+    let mut file = tokio::fs::OpenOptions::new().create(true).append(true).open(path).await?;
+    file.write_all(lines.as_bytes()).await?;
+    file.sync_data().await?;
+
+    Ok(())
+}
+
+/// Reads a finished partial file back as a list of entries.
+pub async fn read_partial<T: DeserializeOwned>(path: &Path) -> Result<Vec<T>> {
+    let data = tokio::fs::read_to_string(path).await?;
+    let mut values = Vec::new();
+    for line in data.lines() {
+        values.push(serde_json::from_str(line)?);
+    }
+
+    Ok(values)
+}
+
+/// Removes a partial file once its entries are part of the final outputs.
+pub async fn remove_partial(path: &Path) -> Result<()> {
+    match tokio::fs::remove_file(path).await {
+        Err(err) if err.kind() != std::io::ErrorKind::NotFound => Err(err.into()),
+        _ => Ok(()),
+    }
+}
@@ -0,0 +1,135 @@
+use subxt::{
+    OnlineClient,
+    SubstrateConfig,
+    backend::{ legacy::LegacyRpcMethods, rpc::RpcClient },
+    utils::H256,
+};
+use sp_core::hashing::twox_128;
+use anyhow::Result;
+use std::collections::HashMap;
+use std::time::Duration;
+
+/// Number of storage keys requested per page, the node's upper limit.
+pub const PAGE_SIZE: u32 = 1000;
+const MAX_BACKOFF_SECS: u64 = 60;
+
+/// Storage key prefix of a map, `twox128(pallet) ++ twox128(entry)`. For plain
+/// storage values this is the full key.
+pub fn storage_prefix(pallet: &str, entry: &str) -> Vec<u8> {
+    [twox_128(pallet.as_bytes()), twox_128(entry.as_bytes())].concat()
+}
+
+/// A node connection that can be re-established after the websocket drops.
+pub struct Client {
+    pub url: String,
+    pub api: OnlineClient<SubstrateConfig>,
+    pub rpc: LegacyRpcMethods<SubstrateConfig>,
+    /// Consecutive failed requests tolerated before giving up.
+    pub retries: u32,
+}
+
+impl Client {
+    pub async fn connect(url: &str, retries: u32) -> Result<Self> {
+        let (api, rpc) = Self::open(url).await?;
+        Ok(Self { url: url.to_string(), api, rpc, retries })
+    }
+
+    /// Opens `connections` connections spread round-robin over `urls`, and
+    /// at least one to each of them.
+    pub async fn connect_all(
+        urls: &[String],
+        connections: usize,
+        retries: u32
+    ) -> Result<Vec<Self>> {
+        let mut clients = Vec::new();
+        for url in urls.iter().cycle().take(connections.max(urls.len())) {
+            clients.push(Self::connect(url, retries).await?);
+        }
+
+        Ok(clients)
+    }
+
+    async fn open(
+        url: &str
+    ) -> Result<(OnlineClient<SubstrateConfig>, LegacyRpcMethods<SubstrateConfig>)> {
+        let rpc_client = RpcClient::from_insecure_url(url).await?;
+        let api = OnlineClient::<SubstrateConfig>::from_rpc_client(rpc_client.clone()).await?;
+        Ok((api, LegacyRpcMethods::<SubstrateConfig>::new(rpc_client)))
+    }
+
+    /// Waits an exponentially growing delay for the given failed attempt and
+    /// then opens a fresh connection.
+    pub async fn reconnect(&mut self, attempt: u32) -> Result<()> {
+        let delay = Duration::from_secs((1u64 << attempt.min(6)).min(MAX_BACKOFF_SECS));
+        println!(
+            "Reconnecting to {} in {}s (attempt {}/{})",
+            self.url,
+            delay.as_secs(),
+            attempt,
+            self.retries
+        );
+        tokio::time::sleep(delay).await;
+
+        let (api, rpc) = Self::open(&self.url).await?;
+        self.api = api;
+        self.rpc = rpc;
+        Ok(())
+    }
+
+    /// Fetches the next page of keys under `prefix`, starting after
+    /// `start_key`, without their values. An empty page means the map has
+    /// been fully read.
+    pub async fn fetch_keys(
+        &self,
+        prefix: &[u8],
+        start_key: Option<&[u8]>,
+        at: H256
+    ) -> Result<Vec<Vec<u8>>> {
+        Ok(self.rpc.state_get_keys_paged(prefix, PAGE_SIZE, start_key, Some(at)).await?)
+    }
+
+    /// Reads the values of `keys` at block `at` in one request, leaving out
+    /// keys that hold nothing.
+    pub async fn fetch_values(
+        &self,
+        keys: Vec<Vec<u8>>,
+        at: H256
+    ) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        if keys.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let mut values: HashMap<Vec<u8>, Vec<u8>> = self.rpc
+            .state_query_storage_at(
+                keys.iter().map(|key| key.as_slice()),
+                Some(at)
+            ).await?
+            .into_iter()
+            .flat_map(|change_set| change_set.changes)
+            .filter_map(|(key, value)| value.map(|value| (key.0, value.0)))
+            .collect();
+
+        Ok(
+            keys
+                .into_iter()
+                .filter_map(|key| values.remove(&key).map(|value| (key, value)))
+                .collect()
+        )
+    }
+
+    /// Fetches the trie nodes that prove the values of `keys` at block `at`.
+    pub async fn fetch_read_proof(&self, keys: &[&[u8]], at: H256) -> Result<Vec<Vec<u8>>> {
+        let proof = self.rpc.state_get_read_proof(keys.iter().copied(), Some(at)).await?;
+        Ok(proof.proof.into_iter().map(|node| node.0).collect())
+    }
+
+    /// Reads the raw value stored under `key` at block `at`.
+    pub async fn fetch_storage(&self, key: &[u8], at: H256) -> Result<Option<Vec<u8>>> {
+        Ok(self.rpc.state_get_storage(key, Some(at)).await?)
+    }
+
+    /// Fetches the SCALE encoded runtime metadata at block `at`.
+    pub async fn fetch_metadata(&self, at: H256) -> Result<Vec<u8>> {
+        Ok(self.rpc.state_get_metadata(Some(at)).await?.into_raw())
+    }
+}
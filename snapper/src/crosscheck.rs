@@ -0,0 +1,235 @@
+//! Comparison of the state several nodes serve at the same block hash, to
+//! catch a stale, forked or lying node before a snapshot is taken from it.
+//!
+//! Every endpoint is asked for the header of the block and for all keys of the
+//! storage a snapshot reads, including the maps of its holding sources. The
+//! values are then compared, either all of them
+//! or an evenly spread sample, always including keys some endpoint lacks.
+use serde::Serialize;
+use parity_scale_codec::Encode;
+use sp_core::hashing::blake2_256;
+use subxt::utils::H256;
+use anyhow::{ Result, anyhow, bail };
+use std::collections::{ BTreeMap, BTreeSet };
+use std::fmt::Write;
+use std::str::FromStr;
+
+use crate::SnapshotBlock;
+use crate::client::{ Client, PAGE_SIZE, storage_prefix };
+use crate::proof::BlockHeader;
+use crate::source::TOTALS_ENTRIES;
+
+/// What one endpoint serves at the checked block.
+#[derive(Serialize, Debug)]
+pub struct EndpointState {
+    pub url: String,
+    /// State root in the endpoint's header of the block
+    pub state_root: String,
+    pub entries: Vec<EntryState>,
+}
+
+/// The keys one endpoint holds under a storage entry.
+#[derive(Serialize, Debug)]
+pub struct EntryState {
+    /// `Pallet.Entry`
+    pub name: String,
+    pub keys: usize,
+    /// blake2_256 over the compared keys the endpoint holds and their values,
+    /// in key order
+    pub values_hash: String,
+}
+
+/// A key that is missing on some endpoint or holds different values.
+#[derive(Serialize, Debug)]
+pub struct KeyMismatch {
+    /// `Pallet.Entry`
+    pub name: String,
+    /// Hex encoded storage key
+    pub key: String,
+    /// Hex encoded value at each endpoint, in the order of the endpoints, `None`
+    /// where the key is missing
+    pub values: Vec<Option<String>>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct Crosscheck {
+    pub block: SnapshotBlock,
+    /// Values compared per storage entry, `None` when all of them were
+    pub sample: Option<usize>,
+    pub endpoints: Vec<EndpointState>,
+    /// The first mismatching keys, by storage entry and key
+    pub mismatches: Vec<KeyMismatch>,
+}
+
+impl Crosscheck {
+    /// Reads `block` from every client and compares what they return for the
+    /// storage `maps` a snapshot crawls and the totals. At most `limit`
+    /// mismatching keys are listed.
+    pub async fn run(
+        clients: &[Client],
+        block: SnapshotBlock,
+        maps: &[(&str, &str)],
+        sample: Option<usize>,
+        limit: usize
+    ) -> Result<Self> {
+        let hash = H256::from_str(&block.hash)?;
+        let mut endpoints = Vec::new();
+        for client in clients {
+            let header = client.rpc
+                .chain_get_header(Some(hash)).await?
+                .ok_or_else(|| anyhow!("{} does not know block {}", client.url, block.hash))?;
+            let header = BlockHeader::new(&header)?;
+            if header.hash != block.hash {
+                bail!("{} returned the header of {} for {}", client.url, header.hash, block.hash);
+            }
+            println!("{}: state root {}", client.url, header.state_root);
+            endpoints.push(EndpointState {
+                url: client.url.clone(),
+                state_root: header.state_root,
+                entries: Vec::new(),
+            });
+        }
+
+        let mut mismatches = Vec::new();
+        for (pallet, entry) in maps.iter().copied().chain(TOTALS_ENTRIES) {
+            let name = format!("{}.{}", pallet, entry);
+            let prefix = storage_prefix(pallet, entry);
+            let keys = futures::future::try_join_all(
+                clients.iter().map(|client| fetch_all_keys(client, &prefix, hash))
+            ).await?;
+            let all = keys.iter().flatten().cloned().collect::<BTreeSet<Vec<u8>>>();
+            let held_anywhere = all.len();
+            let compared = match sample {
+                None => all,
+                Some(size) => {
+                    let step = (all.len() / size.max(1)).max(1);
+                    let missing = all
+                        .iter()
+                        .filter(|key| !keys.iter().all(|held| held.contains(*key)));
+                    all.iter().step_by(step).take(size).chain(missing).cloned().collect()
+                }
+            };
+            let values = futures::future::try_join_all(
+                clients.iter().map(|client| fetch_all_values(client, &compared, hash))
+            ).await?;
+            println!("{}: {} keys, {} values compared", name, held_anywhere, compared.len());
+
+            for (idx, endpoint) in endpoints.iter_mut().enumerate() {
+                let held = values[idx].iter().collect::<Vec<(&Vec<u8>, &Vec<u8>)>>();
+                endpoint.entries.push(EntryState {
+                    name: name.clone(),
+                    keys: keys[idx].len(),
+                    values_hash: format!("0x{}", hex::encode(blake2_256(&held.encode()))),
+                });
+            }
+            for key in &compared {
+                if mismatches.len() >= limit {
+                    break;
+                }
+                let at = values.iter().map(|values| values.get(key)).collect::<Vec<_>>();
+                if at.iter().any(|value| *value != at[0]) {
+                    mismatches.push(KeyMismatch {
+                        name: name.clone(),
+                        key: format!("0x{}", hex::encode(key)),
+                        values: at
+                            .into_iter()
+                            .map(|value| value.map(|value| format!("0x{}", hex::encode(value))))
+                            .collect(),
+                    });
+                }
+            }
+        }
+
+        Ok(Self { block, sample, endpoints, mismatches })
+    }
+
+    /// Whether every endpoint has the same state root, key counts and values.
+    pub fn agrees(&self) -> bool {
+        let first = &self.endpoints[0];
+        self.mismatches.is_empty() &&
+            self.endpoints.iter().all(|endpoint| {
+                endpoint.state_root == first.state_root &&
+                    endpoint.entries
+                        .iter()
+                        .zip(&first.entries)
+                        .all(|(a, b)| a.keys == b.keys && a.values_hash == b.values_hash)
+            })
+    }
+
+    pub fn render_text(&self) -> String {
+        let mut out = String::new();
+        let compared = match self.sample {
+            Some(size) => format!("up to {} values per storage entry compared", size),
+            None => "all values compared".to_string(),
+        };
+        writeln!(
+            out,
+            "Block #{} ({}) at {} endpoints, {}",
+            self.block.number,
+            self.block.hash,
+            self.endpoints.len(),
+            compared
+        ).unwrap();
+        for endpoint in &self.endpoints {
+            writeln!(out, "{}", endpoint.url).unwrap();
+            writeln!(out, "  state root {}", endpoint.state_root).unwrap();
+            for entry in &endpoint.entries {
+                writeln!(
+                    out,
+                    "  {}: {} keys, values {}",
+                    entry.name,
+                    entry.keys,
+                    entry.values_hash
+                ).unwrap();
+            }
+        }
+
+        if self.agrees() {
+            writeln!(out, "All endpoints agree").unwrap();
+        } else if self.mismatches.is_empty() {
+            writeln!(out, "Endpoints disagree, but not on any compared key").unwrap();
+        } else {
+            writeln!(out, "First {} mismatching keys:", self.mismatches.len()).unwrap();
+            for mismatch in &self.mismatches {
+                writeln!(out, "{} {}", mismatch.name, mismatch.key).unwrap();
+                for (endpoint, value) in self.endpoints.iter().zip(&mismatch.values) {
+                    let value = value.as_deref().unwrap_or("missing");
+                    writeln!(out, "  {}: {}", endpoint.url, value).unwrap();
+                }
+            }
+        }
+
+        out
+    }
+}
+
+/// Every key under `prefix` at block `at`.
+async fn fetch_all_keys(client: &Client, prefix: &[u8], at: H256) -> Result<BTreeSet<Vec<u8>>> {
+    let mut keys = BTreeSet::new();
+    let mut start_key: Option<Vec<u8>> = None;
+    loop {
+        let page = client.fetch_keys(prefix, start_key.as_deref(), at).await?;
+        match page.last() {
+            Some(last_key) => start_key = Some(last_key.clone()),
+            None => break,
+        }
+        keys.extend(page);
+    }
+
+    Ok(keys)
+}
+
+/// The values of `keys` at block `at`, a page of keys per request.
+async fn fetch_all_values(
+    client: &Client,
+    keys: &BTreeSet<Vec<u8>>,
+    at: H256
+) -> Result<BTreeMap<Vec<u8>, Vec<u8>>> {
+    let keys = keys.iter().cloned().collect::<Vec<Vec<u8>>>();
+    let mut values = BTreeMap::new();
+    for page in keys.chunks(PAGE_SIZE as usize) {
+        values.extend(client.fetch_values(page.to_vec(), at).await?);
+    }
+
+    Ok(values)
+}
@@ -0,0 +1,203 @@
+use serde::Serialize;
+use clap::ValueEnum;
+use std::collections::{ BTreeMap, BTreeSet };
+use std::fmt::Write;
+
+use crate::SnapshotBlock;
+use crate::profile::NetworkProfile;
+use crate::report::ReportFormat;
+
+#[derive(ValueEnum, Clone, Copy, Debug, Default)]
+pub enum GraphFormat {
+    /// One `from,to,amount` line per StakeTo edge
+    #[default]
+    Csv,
+    Graphml,
+}
+
+/// Stake held by a module or validator, split by who staked it.
+#[derive(Serialize, Debug, Clone, Default)]
+pub struct TargetStake {
+    pub target: String,
+    /// Staked by the target on itself
+    pub self_stake: u128,
+    /// Staked on the target by other addresses
+    pub delegated: u128,
+    pub delegators: usize,
+}
+
+/// Sums StakeTo edges by target, largest delegated stake first, ties broken
+/// by address.
+pub fn aggregate(stake: &[(String, String, u128)]) -> Vec<TargetStake> {
+    let mut targets: BTreeMap<&String, TargetStake> = BTreeMap::new();
+    for (from, to, amount) in stake {
+        let target = targets.entry(to).or_insert_with(|| TargetStake {
+            target: to.clone(),
+            ..Default::default()
+        });
+        if from == to {
+            target.self_stake += amount;
+        } else {
+            target.delegated += amount;
+            target.delegators += 1;
+        }
+    }
+
+    let mut targets: Vec<TargetStake> = targets.into_values().collect();
+    targets.sort_by(|a, b| b.delegated.cmp(&a.delegated).then(a.target.cmp(&b.target)));
+
+    targets
+}
+
+/// Delegation summary of a snapshot, rendered like the balance report.
+#[derive(Serialize, Debug)]
+pub struct StakeReport {
+    pub block: SnapshotBlock,
+    pub edges: usize,
+    pub delegators: usize,
+    pub targets: usize,
+    pub self_stake: u128,
+    pub delegated: u128,
+    pub top: Vec<TargetStake>,
+}
+
+impl StakeReport {
+    pub fn new(stake: &[(String, String, u128)], block: SnapshotBlock, top: usize) -> Self {
+        let targets = aggregate(stake);
+        let delegators = stake
+            .iter()
+            .filter(|(from, to, _)| from != to)
+            .map(|(from, _, _)| from)
+            .collect::<BTreeSet<&String>>();
+
+        Self {
+            block,
+            edges: stake.len(),
+            delegators: delegators.len(),
+            targets: targets.len(),
+            self_stake: targets.iter().map(|target| target.self_stake).sum(),
+            delegated: targets.iter().map(|target| target.delegated).sum(),
+            top: targets.into_iter().take(top).collect(),
+        }
+    }
+
+    pub fn render(&self, format: ReportFormat, profile: &NetworkProfile) -> String {
+        match format {
+            ReportFormat::Text => self.render_text(profile),
+            ReportFormat::Markdown => self.render_markdown(profile),
+            ReportFormat::Json => serde_json::to_string_pretty(self).expect("report serializes"),
+        }
+    }
+
+    fn render_text(&self, profile: &NetworkProfile) -> String {
+        let mut out = String::new();
+        writeln!(out, "Stake at block #{} ({})", self.block.number, self.block.hash).unwrap();
+        writeln!(
+            out,
+            "{} stake entries from {} delegators to {} targets",
+            self.edges,
+            self.delegators,
+            self.targets
+        ).unwrap();
+        writeln!(out, "Self stake: {}", profile.bal(self.self_stake)).unwrap();
+        writeln!(out, "Delegated stake: {}", profile.bal(self.delegated)).unwrap();
+        writeln!(out, "Top {} delegates:", self.top.len()).unwrap();
+        for target in &self.top {
+            writeln!(
+                out,
+                "{}: {} delegated by {} delegators, {} self stake",
+                target.target,
+                profile.bal(target.delegated),
+                target.delegators,
+                profile.bal(target.self_stake)
+            ).unwrap();
+        }
+
+        out
+    }
+
+    fn render_markdown(&self, profile: &NetworkProfile) -> String {
+        let mut out = String::new();
+        writeln!(out, "## Stake report\n").unwrap();
+        writeln!(
+            out,
+            "Taken at block **#{}** (`{}`).\n",
+            self.block.number,
+            self.block.hash
+        ).unwrap();
+        writeln!(out, "| | |").unwrap();
+        writeln!(out, "|---|---:|").unwrap();
+        writeln!(out, "| Stake entries | {} |", self.edges).unwrap();
+        writeln!(out, "| Delegators | {} |", self.delegators).unwrap();
+        writeln!(out, "| Targets | {} |", self.targets).unwrap();
+        writeln!(out, "| Self stake | {} |", profile.bal(self.self_stake)).unwrap();
+        writeln!(out, "| Delegated stake | {} |", profile.bal(self.delegated)).unwrap();
+        writeln!(out).unwrap();
+
+        writeln!(out, "### Top {} delegates\n", self.top.len()).unwrap();
+        writeln!(out, "| # | Target | Delegated | Delegators | Self stake |").unwrap();
+        writeln!(out, "|---:|---|---:|---:|---:|").unwrap();
+        for (idx, target) in self.top.iter().enumerate() {
+            writeln!(
+                out,
+                "| {} | `{}` | {} | {} | {} |",
+                idx + 1,
+                target.target,
+                profile.bal(target.delegated),
+                target.delegators,
+                profile.bal(target.self_stake)
+            ).unwrap();
+        }
+
+        out
+    }
+}
+
+pub fn to_csv(stake: &[(String, String, u128)]) -> String {
+    let mut csv = String::from("from,to,amount\n");
+    for (from, to, amount) in stake {
+        writeln!(csv, "{},{},{}", from, to, amount).unwrap();
+    }
+
+    csv
+}
+
+/// Directed graph with a node per address and a weighted edge per StakeTo
+/// entry. SS58 addresses need no XML escaping.
+pub fn to_graphml(stake: &[(String, String, u128)]) -> String {
+    let targets = aggregate(stake)
+        .into_iter()
+        .map(|target| (target.target.clone(), target))
+        .collect::<BTreeMap<String, TargetStake>>();
+    let nodes = stake
+        .iter()
+        .flat_map(|(from, to, _)| [from, to])
+        .collect::<BTreeSet<&String>>();
+
+    let mut xml = String::new();
+    writeln!(xml, r#"<?xml version="1.0" encoding="UTF-8"?>"#).unwrap();
+    writeln!(xml, r#"<graphml xmlns="http://graphml.graphdrawing.org/xmlns">"#).unwrap();
+    writeln!(xml, r#"  <key id="self_stake" for="node" attr.name="self_stake" attr.type="long"/>"#)
+        .unwrap();
+    writeln!(xml, r#"  <key id="delegated" for="node" attr.name="delegated" attr.type="long"/>"#)
+        .unwrap();
+    writeln!(xml, r#"  <key id="amount" for="edge" attr.name="amount" attr.type="long"/>"#)
+        .unwrap();
+    writeln!(xml, r#"  <graph id="stake" edgedefault="directed">"#).unwrap();
+    for node in nodes {
+        let target = targets.get(node).cloned().unwrap_or_default();
+        writeln!(xml, r#"    <node id="{}">"#, node).unwrap();
+        writeln!(xml, r#"      <data key="self_stake">{}</data>"#, target.self_stake).unwrap();
+        writeln!(xml, r#"      <data key="delegated">{}</data>"#, target.delegated).unwrap();
+        writeln!(xml, r#"    </node>"#).unwrap();
+    }
+    for (from, to, amount) in stake {
+        writeln!(xml, r#"    <edge source="{}" target="{}">"#, from, to).unwrap();
+        writeln!(xml, r#"      <data key="amount">{}</data>"#, amount).unwrap();
+        writeln!(xml, r#"    </edge>"#).unwrap();
+    }
+    writeln!(xml, "  </graph>").unwrap();
+    writeln!(xml, "</graphml>").unwrap();
+
+    xml
+}
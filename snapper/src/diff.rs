@@ -0,0 +1,167 @@
+use std::collections::{ BTreeMap, BTreeSet };
+use std::fmt::Write;
+
+use crate::balances::Balance;
+use crate::profile::NetworkProfile;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ChangeKind {
+    Appeared,
+    Disappeared,
+    Changed,
+    StakeAdded,
+    StakeRemoved,
+    StakeChanged,
+}
+
+impl ChangeKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ChangeKind::Appeared => "appeared",
+            ChangeKind::Disappeared => "disappeared",
+            ChangeKind::Changed => "changed",
+            ChangeKind::StakeAdded => "stake_added",
+            ChangeKind::StakeRemoved => "stake_removed",
+            ChangeKind::StakeChanged => "stake_changed",
+        }
+    }
+}
+
+/// A difference between two snapshots. Balance changes concern the total of
+/// `address`, stake changes the edge from `address` to `target`.
+#[derive(Debug, Clone)]
+pub struct Change {
+    pub kind: ChangeKind,
+    pub address: String,
+    pub target: Option<String>,
+    pub before: u128,
+    pub after: u128,
+}
+
+impl Change {
+    pub fn delta(&self) -> i128 {
+        self.after as i128 - self.before as i128
+    }
+}
+
+/// Lists addresses only present in one of the snapshots and the total
+/// balances that moved by more than `threshold`. Addresses that hold nothing
+/// count as absent.
+pub fn diff_balances(
+    before: &BTreeMap<String, Balance>,
+    after: &BTreeMap<String, Balance>,
+    threshold: u128
+) -> Vec<Change> {
+    let addresses: BTreeSet<&String> = before.keys().chain(after.keys()).collect();
+    fn holder<'a>(balances: &'a BTreeMap<String, Balance>, address: &str) -> Option<&'a Balance> {
+        balances.get(address).filter(|balance| balance.holds_funds())
+    }
+
+    addresses
+        .into_iter()
+        .filter_map(|address| {
+            let (kind, old, new) = match (holder(before, address), holder(after, address)) {
+                (None, Some(new)) => (ChangeKind::Appeared, 0, new.total),
+                (Some(old), None) => (ChangeKind::Disappeared, old.total, 0),
+                (Some(old), Some(new)) if old.total.abs_diff(new.total) > threshold => {
+                    (ChangeKind::Changed, old.total, new.total)
+                }
+                _ => return None,
+            };
+            Some(Change { kind, address: address.clone(), target: None, before: old, after: new })
+        })
+        .collect()
+}
+
+/// Compares the `from -> to` stake edges of two snapshots. Edges that were
+/// added or removed are always listed, changed amounts only above `threshold`.
+pub fn diff_stake(
+    before: &[(String, String, u128)],
+    after: &[(String, String, u128)],
+    threshold: u128
+) -> Vec<Change> {
+    let edges = |stake: &[(String, String, u128)]| -> BTreeMap<(String, String), u128> {
+        let mut edges = BTreeMap::new();
+        for (from, to, staked) in stake {
+            *edges.entry((from.clone(), to.clone())).or_default() += staked;
+        }
+        edges
+    };
+    let (before, after) = (edges(before), edges(after));
+    let pairs: BTreeSet<&(String, String)> = before.keys().chain(after.keys()).collect();
+
+    pairs
+        .into_iter()
+        .filter_map(|pair| {
+            let (kind, old, new) = match (before.get(pair), after.get(pair)) {
+                (None, Some(&new)) => (ChangeKind::StakeAdded, 0, new),
+                (Some(&old), None) => (ChangeKind::StakeRemoved, old, 0),
+                (Some(&old), Some(&new)) if old.abs_diff(new) > threshold => {
+                    (ChangeKind::StakeChanged, old, new)
+                }
+                _ => return None,
+            };
+            Some(Change {
+                kind,
+                address: pair.0.clone(),
+                target: Some(pair.1.clone()),
+                before: old,
+                after: new,
+            })
+        })
+        .collect()
+}
+
+/// Stakers that took stake off one target and put stake on another.
+pub fn redelegations(changes: &[Change]) -> BTreeSet<&String> {
+    let mut decreased = BTreeSet::new();
+    let mut increased = BTreeSet::new();
+    for change in changes.iter().filter(|change| change.target.is_some()) {
+        match change.delta() {
+            delta if delta < 0 => decreased.insert(&change.address),
+            delta if delta > 0 => increased.insert(&change.address),
+            _ => false,
+        };
+    }
+
+    decreased.intersection(&increased).copied().collect()
+}
+
+pub fn to_csv(changes: &[Change]) -> String {
+    let mut csv = String::from("kind,address,target,before,after,delta\n");
+    for change in changes {
+        writeln!(
+            csv,
+            "{},{},{},{},{},{}",
+            change.kind.as_str(),
+            change.address,
+            change.target.as_deref().unwrap_or_default(),
+            change.before,
+            change.after,
+            change.delta()
+        ).unwrap();
+    }
+
+    csv
+}
+
+/// Summary table with the number of changes and the net amount moved per kind.
+pub fn summary(changes: &[Change], profile: &NetworkProfile) -> String {
+    let mut rows: BTreeMap<ChangeKind, (usize, i128)> = BTreeMap::new();
+    for change in changes {
+        let row = rows.entry(change.kind).or_default();
+        row.0 += 1;
+        row.1 += change.delta();
+    }
+
+    let mut out = String::new();
+    writeln!(out, "{:<16}{:>10}{:>24}", "change", "count", "net delta").unwrap();
+    for (kind, (count, delta)) in rows {
+        let sign = if delta < 0 { "-" } else { "" };
+        let amount = format!("{}{}", sign, profile.bal(delta.unsigned_abs()));
+        writeln!(out, "{:<16}{:>10}{:>24}", kind.as_str(), count, amount).unwrap();
+    }
+    writeln!(out, "{:<16}{:>10}", "redelegations", redelegations(changes).len()).unwrap();
+
+    out
+}
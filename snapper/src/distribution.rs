@@ -0,0 +1,213 @@
+//! Concentration statistics over the total balances of a snapshot.
+//!
+//! Everything is computed on the raw u128 amounts. Ratios are taken as exact
+//! integer millionths before they are turned into floats for display, so no
+//! amount ever goes through an f64.
+use serde::Serialize;
+
+/// Percentiles of holders by balance, in tenths of a percent.
+const PERCENTILES: [u32; 7] = [100, 250, 500, 750, 900, 990, 999];
+
+/// Concentration of holdings over every address with a non-zero balance.
+#[derive(Serialize, Debug, Default)]
+pub struct Distribution {
+    pub holders: usize,
+    /// 0 when everyone holds the same, approaching 1 when one address holds
+    /// everything
+    pub gini: f64,
+    /// Fewest holders that together hold at least 33% of the total
+    pub nakamoto_33: usize,
+    /// Fewest holders that together hold at least 51% of the total
+    pub nakamoto_51: usize,
+    pub percentiles: Vec<Percentile>,
+    pub histogram: Vec<Bucket>,
+}
+
+/// Smallest balance that at least `percentile` percent of holders do not
+/// exceed, by nearest rank.
+#[derive(Serialize, Debug)]
+pub struct Percentile {
+    pub percentile: f64,
+    pub balance: u128,
+}
+
+/// Holders with a balance in `[from, to)`, decades of whole tokens. The last
+/// bucket has no upper bound.
+#[derive(Serialize, Debug)]
+pub struct Bucket {
+    pub from: u128,
+    pub to: Option<u128>,
+    pub holders: usize,
+    pub total: u128,
+}
+
+impl Distribution {
+    /// `decimals` places the histogram buckets at whole tokens.
+    pub fn new(balances: impl IntoIterator<Item = u128>, decimals: u32) -> Self {
+        let mut amounts: Vec<u128> = balances
+            .into_iter()
+            .filter(|amount| *amount > 0)
+            .collect();
+        if amounts.is_empty() {
+            return Self::default();
+        }
+        amounts.sort_unstable();
+        let n = amounts.len() as u128;
+        let total: u128 = amounts.iter().sum();
+
+        // G = (2 * sum(i * x_i) - (n + 1) * sum(x_i)) / (n * sum(x_i)) over
+        // the ascending balances, i counting from 1
+        let weighted: u128 = amounts
+            .iter()
+            .enumerate()
+            .map(|(idx, amount)| (idx as u128 + 1) * amount)
+            .sum();
+        let gini = millionths(2 * weighted - (n + 1) * total, n * total);
+
+        Self {
+            holders: amounts.len(),
+            gini: (gini as f64) / 1e6,
+            nakamoto_33: nakamoto(&amounts, total, 33),
+            nakamoto_51: nakamoto(&amounts, total, 51),
+            percentiles: PERCENTILES.iter()
+                .map(|permille| {
+                    let rank = (*permille as u128 * n).div_ceil(1000).max(1);
+                    Percentile {
+                        percentile: (*permille as f64) / 10.0,
+                        balance: amounts[rank as usize - 1],
+                    }
+                })
+                .collect(),
+            histogram: histogram(&amounts, 10u128.pow(decimals)),
+        }
+    }
+}
+
+/// `part / whole` in millionths, rounded down. Amounts too large for the
+/// exact product only occur far beyond any real supply, where the f64 ratio
+/// is as precise as the result.
+fn millionths(part: u128, whole: u128) -> u128 {
+    match part.checked_mul(1_000_000) {
+        Some(scaled) => scaled / whole,
+        None => ((part as f64 / whole as f64) * 1e6) as u128,
+    }
+}
+
+/// `part` as a percentage of `whole`, exact to four decimal places.
+pub fn percent(part: u128, whole: u128) -> f64 {
+    (millionths(part, whole) as f64) / 1e4
+}
+
+/// Number of largest holders needed to reach `percent` of `total`, given the
+/// balances in ascending order.
+fn nakamoto(amounts: &[u128], total: u128, percent: u128) -> usize {
+    let mut held = 0;
+    for (idx, amount) in amounts.iter().rev().enumerate() {
+        held += amount;
+        if held * 100 >= total * percent {
+            return idx + 1;
+        }
+    }
+
+    amounts.len()
+}
+
+/// Buckets below one token, then one per decade of tokens up to the largest
+/// balance, empty decades included.
+fn histogram(amounts: &[u128], unit: u128) -> Vec<Bucket> {
+    let largest = *amounts.last().expect("amounts are not empty");
+    let mut bounds = vec![0, unit];
+    while let Some(next) = bounds.last().unwrap().checked_mul(10) && next <= largest {
+        bounds.push(next);
+    }
+
+    let mut buckets: Vec<Bucket> = bounds
+        .iter()
+        .enumerate()
+        .map(|(idx, from)| Bucket {
+            from: *from,
+            to: bounds.get(idx + 1).copied(),
+            holders: 0,
+            total: 0,
+        })
+        .collect();
+    for amount in amounts {
+        let idx = bounds.partition_point(|bound| bound <= amount) - 1;
+        buckets[idx].holders += 1;
+        buckets[idx].total += amount;
+    }
+
+    buckets
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn equal_holders() {
+        let distribution = Distribution::new([10, 10, 10], 0);
+
+        assert_eq!(distribution.holders, 3);
+        assert_eq!(distribution.gini, 0.0);
+        assert_eq!((distribution.nakamoto_33, distribution.nakamoto_51), (1, 2));
+    }
+
+    #[test]
+    fn concentrated_holders() {
+        let distribution = Distribution::new([1, 0, 1, 97, 1], 0);
+
+        // Empty balances are not holders
+        assert_eq!(distribution.holders, 4);
+        assert_eq!(distribution.gini, 0.72);
+        assert_eq!((distribution.nakamoto_33, distribution.nakamoto_51), (1, 1));
+    }
+
+    #[test]
+    fn percentiles_by_nearest_rank() {
+        let distribution = Distribution::new(1..=10, 0);
+        let balances = distribution.percentiles
+            .iter()
+            .map(|percentile| (percentile.percentile, percentile.balance))
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            balances,
+            vec![(10.0, 1), (25.0, 3), (50.0, 5), (75.0, 8), (90.0, 9), (99.0, 10), (99.9, 10)]
+        );
+    }
+
+    #[test]
+    fn histogram_by_decades_of_tokens() {
+        let distribution = Distribution::new([5, 1_000, 5_000, 10_000, 150_000], 3);
+        let buckets = distribution.histogram
+            .iter()
+            .map(|bucket| (bucket.from, bucket.to, bucket.holders, bucket.total))
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            buckets,
+            vec![
+                (0, Some(1_000), 1, 5),
+                (1_000, Some(10_000), 2, 6_000),
+                (10_000, Some(100_000), 1, 10_000),
+                (100_000, None, 1, 150_000),
+            ]
+        );
+    }
+
+    #[test]
+    fn no_holders() {
+        let distribution = Distribution::new([0, 0], 9);
+
+        assert_eq!(distribution.holders, 0);
+        assert!(distribution.percentiles.is_empty() && distribution.histogram.is_empty());
+    }
+
+    #[test]
+    fn percent_is_exact_to_four_decimals() {
+        assert_eq!(percent(1, 3), 33.3333);
+        assert_eq!(percent(1, 1_000_000_000_000_000_000), 0.0);
+        assert_eq!(percent(u128::MAX / 2, u128::MAX), 50.0);
+    }
+}
@@ -0,0 +1,75 @@
+//! Raw key/value state exports, read instead of a live node so that a
+//! snapshot can be reproduced offline. Accepted layouts are:
+//! - a `state_getPairs` result, `[["0xkey", "0xvalue"], ...]`, optionally
+//!   still wrapped in its JSON-RPC response;
+//! - a raw chain-spec, whose `genesis.raw.top` holds the genesis state;
+//! - a bare `{ "0xkey": "0xvalue" }` object, e.g. a copy of `raw.top`.
+use serde_json::Value;
+use anyhow::{ Result, anyhow, bail };
+use std::collections::BTreeMap;
+use std::path::Path;
+
+/// Every key/value pair of a state export, ordered by key like the trie.
+pub struct StateDump {
+    pairs: BTreeMap<Vec<u8>, Vec<u8>>,
+}
+
+impl StateDump {
+    pub async fn load(path: &Path) -> Result<Self> {
+        let json: Value = serde_json::from_str(&tokio::fs::read_to_string(path).await?)?;
+        let dump = json.get("result").unwrap_or(&json);
+        let dump = dump.pointer("/genesis/raw/top").unwrap_or(dump);
+
+        let mut pairs = BTreeMap::new();
+        let mut insert = |key: &Value, value: &Value| -> Result<()> {
+            let (Some(key), Some(value)) = (key.as_str(), value.as_str()) else {
+                bail!("{} holds a pair that is not a pair of hex strings", path.display());
+            };
+            pairs.insert(from_hex(key)?, from_hex(value)?);
+            Ok(())
+        };
+        match dump {
+            Value::Array(list) => {
+                for pair in list {
+                    match pair.as_array().map(|pair| pair.as_slice()) {
+                        Some([key, value]) => insert(key, value)?,
+                        _ => bail!("{} holds an entry that is not a pair", path.display()),
+                    }
+                }
+            }
+            Value::Object(map) => {
+                for (key, value) in map {
+                    insert(&Value::String(key.clone()), value)?;
+                }
+            }
+            _ => bail!("{} is not a state dump or a raw chain-spec", path.display()),
+        }
+
+        Ok(Self { pairs })
+    }
+
+    pub fn pair_count(&self) -> usize {
+        self.pairs.len()
+    }
+
+    pub fn get(&self, key: &[u8]) -> Option<&[u8]> {
+        self.pairs.get(key).map(|value| value.as_slice())
+    }
+
+    /// Pairs whose key starts with `prefix`, in key order.
+    pub fn prefixed<'a>(
+        &'a self,
+        prefix: &[u8]
+    ) -> impl Iterator<Item = (&'a [u8], &'a [u8])> + use<'a> {
+        let prefix = prefix.to_vec();
+        self.pairs
+            .range(prefix.clone()..)
+            .take_while(move |(key, _)| key.starts_with(&prefix))
+            .map(|(key, value)| (key.as_slice(), value.as_slice()))
+    }
+}
+
+fn from_hex(value: &str) -> Result<Vec<u8>> {
+    hex::decode(value.trim_start_matches("0x"))
+        .map_err(|err| anyhow!("Invalid hex {}: {}", value, err))
+}
@@ -0,0 +1,88 @@
+use serde::Serialize;
+use anyhow::{ Result, anyhow, bail };
+use std::fmt;
+use std::str::FromStr;
+
+use crate::profile::NetworkProfile;
+
+/// What happens to balances below the target's existential deposit.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub enum DustStrategy {
+    /// Leaves the accounts out, burning their balances.
+    #[default]
+    DropBurn,
+    /// Leaves the accounts out and credits their balances to one address.
+    Sweep(String),
+    /// Raises the balances to the existential deposit, minting the
+    /// difference. Empty accounts are still left out.
+    RoundUp,
+}
+
+impl FromStr for DustStrategy {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> Result<Self> {
+        match value {
+            "drop-burn" => Ok(Self::DropBurn),
+            "round-up" => Ok(Self::RoundUp),
+            value => match value.strip_prefix("sweep:") {
+                Some("") => bail!("sweep needs an address, e.g. sweep:<address>"),
+                Some(address) => Ok(Self::Sweep(address.to_string())),
+                None => Err(anyhow!(
+                    "Unknown dust strategy '{}', expected drop-burn, sweep:<address> or round-up",
+                    value
+                )),
+            },
+        }
+    }
+}
+
+impl fmt::Display for DustStrategy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::DropBurn => write!(f, "drop-burn"),
+            Self::Sweep(address) => write!(f, "sweep:{}", address),
+            Self::RoundUp => write!(f, "round-up"),
+        }
+    }
+}
+
+/// How a dust strategy changed the account count and total issuance of the
+/// target chain, amounts in the target's smallest unit.
+#[derive(Serialize, Debug, Default)]
+pub struct DustEffect {
+    pub strategy: String,
+    pub existential_deposit: u128,
+    /// Accounts below the existential deposit and what they held
+    pub dust_accounts: usize,
+    pub dust_total: u128,
+    pub accounts_before: usize,
+    pub accounts_after: usize,
+    pub issuance_before: u128,
+    pub issuance_after: u128,
+}
+
+impl DustEffect {
+    pub fn render(&self, target: &NetworkProfile) -> String {
+        let change = if self.issuance_after >= self.issuance_before {
+            format!("+{}", target.bal(self.issuance_after - self.issuance_before))
+        } else {
+            format!("-{}", target.bal(self.issuance_before - self.issuance_after))
+        };
+
+        format!(
+            "Dust strategy {}: {} accounts below the existential deposit of {} held {}\n\
+             Accounts: {} -> {}\n\
+             Total issuance: {} -> {} ({})",
+            self.strategy,
+            self.dust_accounts,
+            target.bal(self.existential_deposit),
+            target.bal(self.dust_total),
+            self.accounts_before,
+            self.accounts_after,
+            target.bal(self.issuance_before),
+            target.bal(self.issuance_after),
+            change
+        )
+    }
+}
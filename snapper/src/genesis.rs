@@ -0,0 +1,180 @@
+use anyhow::{ Result, anyhow, bail };
+use serde_json::{ Value, json };
+use std::collections::BTreeMap;
+
+use crate::balances::Balance;
+use crate::dust::{ DustEffect, DustStrategy };
+use crate::profile::NetworkProfile;
+
+/// Where the balances pallet config lives in plain chain-specs, newest layout first.
+const BALANCES_PATHS: [&[&str]; 3] = [
+    &["genesis", "runtimeGenesis", "patch", "balances"],
+    &["genesis", "runtimeGenesis", "config", "balances"],
+    &["genesis", "runtime", "balances"],
+];
+
+/// Genesis balances of the target chain, along with what the dust strategy
+/// did to them.
+#[derive(Debug, Default)]
+pub struct GenesisBalances {
+    pub balances: Vec<(String, u128)>,
+    pub total: u128,
+    pub dust: DustEffect,
+}
+
+/// Converts snapshot totals into target chain balances: amounts are scaled to
+/// the target's decimals, addresses re-encoded with its SS58 prefix and
+/// accounts below its existential deposit handled by `strategy`.
+pub fn genesis_balances(
+    balances: &BTreeMap<String, Balance>,
+    source: &NetworkProfile,
+    target: &NetworkProfile,
+    strategy: &DustStrategy
+) -> Result<GenesisBalances> {
+    let mut genesis = GenesisBalances::default();
+    let mut dust = DustEffect {
+        strategy: strategy.to_string(),
+        existential_deposit: target.existential_deposit,
+        ..Default::default()
+    };
+    let mut kept = BTreeMap::new();
+
+    for (address, balance) in balances.iter().filter(|(_, balance)| balance.holds_funds()) {
+        let mut amount = target.convert_amount(balance.total, source)?;
+        let address = target.encode_address(NetworkProfile::decode_address(address)?);
+        dust.accounts_before += 1;
+        dust.issuance_before += amount;
+
+        if amount < target.existential_deposit {
+            dust.dust_accounts += 1;
+            dust.dust_total += amount;
+            match strategy {
+                DustStrategy::RoundUp if amount > 0 => amount = target.existential_deposit,
+                _ => continue,
+            }
+        }
+        kept.insert(address, amount);
+    }
+
+    if let DustStrategy::Sweep(recipient) = strategy && dust.dust_total > 0 {
+        let recipient = target.encode_address(NetworkProfile::decode_address(recipient)?);
+        let swept = kept.entry(recipient.clone()).or_insert(0);
+        *swept += dust.dust_total;
+        if *swept < target.existential_deposit {
+            bail!(
+                "Sweeping {} of dust leaves {} below the existential deposit",
+                dust.dust_total,
+                recipient
+            );
+        }
+    }
+
+    dust.accounts_after = kept.len();
+    dust.issuance_after = kept.values().sum();
+    genesis.total = dust.issuance_after;
+    genesis.balances = kept.into_iter().collect();
+    genesis.dust = dust;
+
+    Ok(genesis)
+}
+
+/// Replaces the balances of the balances pallet config in a plain chain-spec.
+/// Returns the number of balances the spec had before.
+pub fn patch_chain_spec(spec: &mut Value, balances: &[(String, u128)]) -> Result<usize> {
+    if spec.pointer("/genesis/raw").is_some() {
+        bail!("Raw chain-specs cannot be patched, patch the plain spec and convert it to raw");
+    }
+
+    let path = BALANCES_PATHS.iter()
+        .find(|path| lookup(spec, path).is_some())
+        .ok_or_else(|| anyhow!("Chain-spec has no balances pallet config"))?;
+    let config = lookup_mut(spec, path).expect("path was found above");
+    let replaced = config
+        .get("balances")
+        .and_then(|existing| existing.as_array())
+        .map(|existing| existing.len())
+        .unwrap_or(0);
+    config["balances"] = json!(balances);
+
+    Ok(replaced)
+}
+
+fn lookup<'a>(value: &'a Value, path: &[&str]) -> Option<&'a Value> {
+    path.iter().try_fold(value, |value, key| value.get(key))
+}
+
+fn lookup_mut<'a>(value: &'a mut Value, path: &[&str]) -> Option<&'a mut Value> {
+    path.iter().try_fold(value, |value, key| value.get_mut(key))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn profile(ss58_prefix: u16, decimals: u32, existential_deposit: u128) -> NetworkProfile {
+        NetworkProfile {
+            url: String::new(),
+            ss58_prefix,
+            decimals,
+            existential_deposit,
+            sources: Vec::new(),
+        }
+    }
+
+    /// Balances of the accounts `[1; 32]` to `[4; 32]` on a 9 decimal chain,
+    /// converted to a 12 decimal target with an existential deposit of 1e6.
+    /// The last account is a stake target without funds of its own.
+    fn run(strategy: DustStrategy) -> Result<GenesisBalances> {
+        let source = profile(42, 9, 500);
+        let target = profile(42, 12, 1_000_000);
+        let mut balances = BTreeMap::new();
+        for (byte, free, staked_in) in [(1, 10_000, 0), (2, 200, 0), (3, 1, 0), (4, 0, 700)] {
+            let mut balance = Balance { free, staked_in, ..Default::default() };
+            balance.update_total();
+            balances.insert(source.encode_address([byte; 32]), balance);
+        }
+
+        genesis_balances(&balances, &source, &target, &strategy)
+    }
+
+    fn address(byte: u8) -> String {
+        profile(42, 12, 0).encode_address([byte; 32])
+    }
+
+    #[test]
+    fn drop_burn_leaves_dust_out() {
+        let genesis = run(DustStrategy::DropBurn).unwrap();
+
+        assert_eq!(genesis.balances, vec![(address(1), 10_000_000)]);
+        assert_eq!(genesis.total, 10_000_000);
+        assert_eq!(genesis.dust.accounts_before, 3);
+        assert_eq!(genesis.dust.dust_accounts, 2);
+        assert_eq!(genesis.dust.dust_total, 201_000);
+        assert_eq!(genesis.dust.issuance_before, 10_201_000);
+    }
+
+    #[test]
+    fn sweep_credits_dust_to_the_recipient() {
+        let genesis = run(DustStrategy::Sweep(address(1))).unwrap();
+
+        assert_eq!(genesis.balances, vec![(address(1), 10_201_000)]);
+        assert_eq!(genesis.total, genesis.dust.issuance_before);
+    }
+
+    #[test]
+    fn sweep_below_existential_deposit_fails() {
+        assert!(run(DustStrategy::Sweep(address(9))).is_err());
+    }
+
+    #[test]
+    fn round_up_mints_up_to_existential_deposit() {
+        let genesis = run(DustStrategy::RoundUp).unwrap();
+
+        assert_eq!(
+            genesis.balances,
+            vec![(address(1), 10_000_000), (address(2), 1_000_000), (address(3), 1_000_000)]
+        );
+        assert_eq!(genesis.total, 12_000_000);
+        assert_eq!(genesis.dust.accounts_after, 3);
+    }
+}
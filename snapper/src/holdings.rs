@@ -0,0 +1,363 @@
+//! Value that belongs to an address but sits outside its account and its
+//! `StakeTo` entries. Each holding source reads one place such value is kept
+//! and credits it to the address it belongs to. A network profile lists the
+//! sources its snapshots read, which `snap --source` overrides.
+//!
+//! Registering a subnet burns its cost instead of locking it in this runtime,
+//! so there are no subnet registration locks to read.
+use serde::{ Serialize, Deserialize };
+use clap::ValueEnum;
+use scale_value::At;
+use parity_scale_codec::Decode;
+use anyhow::{ Result, anyhow, bail };
+use std::collections::{ BTreeMap, HashMap };
+use std::fmt;
+use std::path::Path;
+
+use crate::metadata::{ ChainMetadata, account_id, field_u128, variant_name };
+use crate::profile::NetworkProfile;
+use crate::snapshot::DecodeFailure;
+use crate::source::{ decode_entries, decode_stake_from };
+
+pub const HOLDINGS_FILE: &str = "holdings.json";
+
+#[derive(
+    ValueEnum,
+    Serialize,
+    Deserialize,
+    Clone,
+    Copy,
+    Debug,
+    PartialEq,
+    Eq,
+    PartialOrd,
+    Ord,
+    Hash
+)]
+#[serde(rename_all = "kebab-case")]
+pub enum HoldingSource {
+    /// Stake in the `StakeFrom` mirror beyond what `StakeTo` records for the
+    /// same staker and module, credited to the staker. The two maps normally
+    /// agree, so this only finds stake the snapshot would otherwise miss.
+    StakeFrom,
+    /// Cost of governance proposals that are still open, refunded to the
+    /// proposer when a proposal is accepted
+    Proposals,
+    /// Cost of curator applications that are still pending, credited to the
+    /// account that paid for the application
+    CuratorApplications,
+    /// Emission subnets have accumulated but not distributed yet. The founder
+    /// share of it is credited to the subnet founder, the rest goes to the
+    /// subnet's modules on distribution and is reported as unattributed.
+    PendingEmission,
+}
+
+impl HoldingSource {
+    /// Storage entries the source reads, as `(pallet, entry)`.
+    pub fn entries(self) -> &'static [(&'static str, &'static str)] {
+        match self {
+            Self::StakeFrom => &[("SubspaceModule", "StakeFrom")],
+            Self::Proposals => &[("GovernanceModule", "Proposals")],
+            Self::CuratorApplications => &[("GovernanceModule", "CuratorApplications")],
+            Self::PendingEmission => &[
+                ("SubnetEmissionModule", "PendingEmission"),
+                ("SubspaceModule", "Founder"),
+                ("SubspaceModule", "FounderShare"),
+            ],
+        }
+    }
+}
+
+impl fmt::Display for HoldingSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = self.to_possible_value().expect("no source is skipped");
+        write!(f, "{}", name.get_name())
+    }
+}
+
+/// An amount a holding source credits to an address.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Holding {
+    pub source: HoldingSource,
+    pub address: String,
+    pub amount: u128,
+}
+
+/// What the holding sources of a snapshot found, saved as holdings.json.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct Holdings {
+    /// Sources that were read, including those that found nothing
+    pub sources: Vec<HoldingSource>,
+    pub holdings: Vec<Holding>,
+    /// Amounts a source found that belong to no single address yet, kept out
+    /// of the balances
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub unattributed: BTreeMap<HoldingSource, u128>,
+}
+
+impl Holdings {
+    /// Snapshots read without any source have no holdings.json.
+    pub async fn save(&self, dir: &Path) -> Result<()> {
+        if self.sources.is_empty() {
+            return Ok(());
+        }
+        let json = serde_json::to_string_pretty(self)?;
+        tokio::fs::write(dir.join(HOLDINGS_FILE), json).await?;
+
+        Ok(())
+    }
+
+    pub async fn load(dir: &Path) -> Result<Self> {
+        match tokio::fs::read_to_string(dir.join(HOLDINGS_FILE)).await {
+            Ok(json) => Ok(serde_json::from_str(&json)?),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(err) => Err(err.into()),
+        }
+    }
+}
+
+/// Storage entries the given sources read, each once, as `(pallet, entry)`.
+pub fn source_entries(sources: &[HoldingSource]) -> Vec<(&'static str, &'static str)> {
+    let mut entries = Vec::new();
+    for entry in sources.iter().flat_map(|source| source.entries()) {
+        if !entries.contains(entry) {
+            entries.push(*entry);
+        }
+    }
+
+    entries
+}
+
+/// Raw entries of the storage the sources read, by `Pallet.Entry`, in key
+/// order.
+pub type RawEntries = BTreeMap<String, Vec<(Vec<u8>, Vec<u8>)>>;
+
+/// Decodes the entries the `sources` read into holdings, in the order of the
+/// sources and then of the storage keys. `stake` holds the `StakeTo` edges the
+/// `StakeFrom` mirror is compared with.
+///
+/// Entries that fail to decode abort, unless `lenient` is set, in which case
+/// they are added to `errors`.
+pub fn collect_holdings(
+    metadata: &ChainMetadata,
+    profile: &NetworkProfile,
+    sources: &[HoldingSource],
+    stake: &[(String, String, u128)],
+    raw: &RawEntries,
+    lenient: bool,
+    errors: &mut Vec<DecodeFailure>
+) -> Result<Holdings> {
+    let mut holdings = Vec::new();
+    let mut unattributed = BTreeMap::new();
+    for source in sources {
+        let credit = |address: [u8; 32], amount: u128| Holding {
+            source: *source,
+            address: profile.encode_address(address),
+            amount,
+        };
+        let found = match source {
+            HoldingSource::StakeFrom => {
+                let staked = stake
+                    .iter()
+                    .map(|(from, to, amount)| ((from.as_str(), to.as_str()), *amount))
+                    .collect::<HashMap<(&str, &str), u128>>();
+                let entry = ("SubspaceModule", "StakeFrom");
+                decode_raw(raw, entry, lenient, errors, |key, value| {
+                    let (staker, module, amount) =
+                        decode_stake_from(metadata, profile, key, value)?;
+                    let recorded = staked
+                        .get(&(staker.as_str(), module.as_str()))
+                        .copied()
+                        .unwrap_or_default();
+                    Ok(
+                        (amount > recorded).then(|| Holding {
+                            source: *source,
+                            address: staker.clone(),
+                            amount: amount - recorded,
+                        })
+                    )
+                })?
+            }
+            HoldingSource::Proposals => {
+                decode_raw(raw, ("GovernanceModule", "Proposals"), lenient, errors, |_, value| {
+                    let proposal = metadata.decode_value("GovernanceModule", "Proposals", value)?;
+                    if variant_name(&proposal, "status") != Some("Open") {
+                        return Ok(None);
+                    }
+                    let proposer = proposal
+                        .at("proposer")
+                        .and_then(account_id)
+                        .ok_or_else(|| anyhow!("Proposal has no proposer"))?;
+                    let cost = field_u128(&proposal, "proposal_cost")
+                        .ok_or_else(|| anyhow!("Proposal has no proposal_cost"))?;
+                    Ok(Some(credit(proposer, cost)))
+                })?
+            }
+            HoldingSource::CuratorApplications => {
+                let entry = ("GovernanceModule", "CuratorApplications");
+                decode_raw(raw, entry, lenient, errors, |_, value| {
+                    let application = metadata
+                        .decode_value("GovernanceModule", "CuratorApplications", value)?;
+                    if variant_name(&application, "status") != Some("Pending") {
+                        return Ok(None);
+                    }
+                    let payer = application
+                        .at("paying_for")
+                        .and_then(account_id)
+                        .ok_or_else(|| anyhow!("Curator application has no paying_for"))?;
+                    let cost = field_u128(&application, "application_cost")
+                        .ok_or_else(|| anyhow!("Curator application has no application_cost"))?;
+                    Ok(Some(credit(payer, cost)))
+                })?
+            }
+            HoldingSource::PendingEmission => {
+                let entry = ("SubspaceModule", "Founder");
+                let founders = decode_raw(raw, entry, lenient, errors, |key, value| {
+                    let netuid = key_netuid(metadata, entry, key)?;
+                    let founder = <[u8; 32]>::try_from(value)
+                        .map_err(|_| anyhow!("Founder is not an account id"))?;
+                    Ok(Some((netuid, founder)))
+                })?
+                    .into_iter()
+                    .collect::<HashMap<u16, [u8; 32]>>();
+                let entry = ("SubspaceModule", "FounderShare");
+                let shares = decode_raw(raw, entry, lenient, errors, |key, value| {
+                    let netuid = key_netuid(metadata, entry, key)?;
+                    let share = metadata
+                        .decode_value("SubspaceModule", "FounderShare", value)?
+                        .as_u128()
+                        .ok_or_else(|| anyhow!("FounderShare is not an unsigned integer"))?;
+                    Ok(Some((netuid, share)))
+                })?
+                    .into_iter()
+                    .collect::<HashMap<u16, u128>>();
+                let default_share = metadata
+                    .default_value("SubspaceModule", "FounderShare")?
+                    .as_u128()
+                    .ok_or_else(|| anyhow!("FounderShare is not an unsigned integer"))?;
+                let entry = ("SubnetEmissionModule", "PendingEmission");
+                let split = decode_raw(raw, entry, lenient, errors, |key, value| {
+                    let netuid = key_netuid(metadata, entry, key)?;
+                    let pending = metadata
+                        .decode_value("SubnetEmissionModule", "PendingEmission", value)?
+                        .as_u128()
+                        .ok_or_else(|| anyhow!("PendingEmission is not an unsigned integer"))?;
+                    if pending == 0 {
+                        return Ok(None);
+                    }
+                    let founder = founders
+                        .get(&netuid)
+                        .ok_or_else(|| anyhow!("Subnet {} has no founder", netuid))?;
+                    // Percent of the emission the founder receives
+                    let share = shares
+                        .get(&netuid)
+                        .copied()
+                        .unwrap_or(default_share)
+                        .min(100);
+                    let founded = pending * share / 100;
+                    Ok(Some((credit(*founder, founded), pending - founded)))
+                })?;
+                let rest = split.iter().map(|(_, rest)| rest).sum::<u128>();
+                if rest > 0 {
+                    unattributed.insert(*source, rest);
+                }
+                split
+                    .into_iter()
+                    .map(|(holding, _)| holding)
+                    .filter(|holding| holding.amount > 0)
+                    .collect()
+            }
+        };
+        holdings.extend(found);
+    }
+
+    Ok(Holdings { sources: sources.to_vec(), holdings, unattributed })
+}
+
+/// Decodes the raw entries of `pallet.entry` like a snapshot decodes its
+/// maps, keeping those `decode` finds a holding in.
+fn decode_raw<T>(
+    raw: &RawEntries,
+    (pallet, entry): (&str, &str),
+    lenient: bool,
+    errors: &mut Vec<DecodeFailure>,
+    decode: impl FnMut(&[u8], &[u8]) -> Result<Option<T>>
+) -> Result<Vec<T>> {
+    let name = format!("{}.{}", pallet, entry);
+    let entries = raw.get(&name).map(Vec::as_slice).unwrap_or_default();
+    let found = decode_entries(
+        &name,
+        entries.iter().map(|(key, value)| (key.as_slice(), value.as_slice())),
+        lenient,
+        errors,
+        decode
+    )?;
+
+    Ok(found.into_iter().flatten().collect())
+}
+
+/// Reads the subnet id a storage map is keyed by.
+fn key_netuid(metadata: &ChainMetadata, (pallet, entry): (&str, &str), key: &[u8]) -> Result<u16> {
+    match metadata.decode_key(pallet, entry, key)?.as_slice() {
+        [netuid] => Ok(u16::decode(&mut netuid.as_slice())?),
+        parts => bail!("{}.{} keys have {} parts, not 1", pallet, entry, parts.len()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::client::storage_prefix;
+
+    /// Pending emission of subnet 0, with a founder share of 12 percent, and
+    /// of subnet 1, which falls back to the default share of 16 percent.
+    #[test]
+    fn pending_emission_is_split_by_founder_share() {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("metadata.commune.scale");
+        let metadata = ChainMetadata::decode(&std::fs::read(path).unwrap()).unwrap();
+        let profile = NetworkProfile::load(None, "commune").unwrap();
+        let key = |pallet: &str, entry: &str, netuid: u16| {
+            [storage_prefix(pallet, entry), netuid.to_le_bytes().to_vec()].concat()
+        };
+        let mut raw = RawEntries::new();
+        for (netuid, founder, pending) in [(0u16, 2u8, 5000u64), (1, 3, 3001)] {
+            raw.entry("SubspaceModule.Founder".to_string())
+                .or_default()
+                .push((key("SubspaceModule", "Founder", netuid), vec![founder; 32]));
+            raw.entry("SubnetEmissionModule.PendingEmission".to_string())
+                .or_default()
+                .push((
+                    key("SubnetEmissionModule", "PendingEmission", netuid),
+                    pending.to_le_bytes().to_vec(),
+                ));
+        }
+        raw.insert(
+            "SubspaceModule.FounderShare".to_string(),
+            vec![(key("SubspaceModule", "FounderShare", 0), 12u16.to_le_bytes().to_vec())]
+        );
+
+        let sources = [HoldingSource::PendingEmission];
+        let mut errors = Vec::new();
+        let holdings = collect_holdings(
+            &metadata,
+            &profile,
+            &sources,
+            &[],
+            &raw,
+            false,
+            &mut errors
+        ).unwrap();
+
+        let credited = holdings.holdings
+            .iter()
+            .map(|holding| (holding.address.clone(), holding.amount))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            credited,
+            vec![(profile.encode_address([2; 32]), 600), (profile.encode_address([3; 32]), 480)]
+        );
+        assert_eq!(holdings.unattributed[&HoldingSource::PendingEmission], 5000 + 3001 - 1080);
+        assert!(errors.is_empty());
+    }
+}
@@ -0,0 +1,52 @@
+//! Snapshots of the Commune chain: account balances and stake read at a
+//! pinned block, from a live node or offline from a state dump, and the
+//! reports, reconciliation, genesis and Merkle outputs built from them.
+//!
+//! A [`SnapshotSource`] produces a [`Snapshot`], which owns the decoded
+//! accounts, stake edges and the metadata they were decoded against:
+//!
+//! ```
+//! # #[tokio::main]
+//! # async fn main() -> anyhow::Result<()> {
+//! use std::path::Path;
+//! use snapper::{ DumpSource, NetworkProfile, SnapshotSource };
+//!
+//! let profile = NetworkProfile::load(None, "commune")?;
+//! let mut source = DumpSource::load(
+//!     Path::new("tests/fixtures/chain-spec-raw.json"),
+//!     Path::new("metadata.commune.scale"),
+//!     0
+//! ).await?;
+//! let snapshot = source.snapshot(&profile, false).await?;
+//! let balances = snapshot.balances();
+//! # assert_eq!(balances.len(), 3);
+//! # Ok(())
+//! # }
+//! ```
+pub mod balances;
+pub mod checkpoint;
+pub mod client;
+pub mod crosscheck;
+pub mod delegation;
+pub mod diff;
+pub mod distribution;
+pub mod dump;
+pub mod dust;
+pub mod genesis;
+pub mod holdings;
+pub mod manifest;
+pub mod merkle;
+pub mod metadata;
+pub mod policy;
+pub mod profile;
+pub mod proof;
+pub mod reconcile;
+pub mod report;
+pub mod series;
+pub mod signature;
+pub mod snapshot;
+pub mod source;
+
+pub use profile::NetworkProfile;
+pub use snapshot::{ Account, AccountData, DecodeFailure, Snapshot, SnapshotBlock };
+pub use source::{ DumpSource, FixtureSource, LiveSource, SnapshotSource };
@@ -1,9 +1,50 @@
-use serde::{ Serialize, Deserialize };
-use subxt::{ OnlineClient, SubstrateConfig, utils::AccountId32 };
-use anyhow::Result;
-use std::collections::HashMap;
+use sp_core::{ Pair, sr25519 };
+use anyhow::{ Result, anyhow, bail };
+use std::collections::{ BTreeMap, BTreeSet };
+use std::path::{ Path, PathBuf };
 use clap::{ Parser, Subcommand };
 
+use snapper::balances::{ Balance, load_balances, map_balances, save_balances };
+use snapper::checkpoint::{ CHECKPOINT_FILE, append_partial, read_partial };
+use snapper::client::{ Client, storage_prefix };
+use snapper::crosscheck::Crosscheck;
+use snapper::delegation::{ self, GraphFormat, StakeReport };
+use snapper::diff::{ self, diff_balances, diff_stake };
+use snapper::dust::DustStrategy;
+use snapper::genesis::{ genesis_balances, patch_chain_spec };
+use snapper::holdings::{
+    HOLDINGS_FILE,
+    HoldingSource,
+    Holdings,
+    RawEntries,
+    collect_holdings,
+};
+use snapper::manifest::{
+    MANIFEST_FILE,
+    Manifest,
+    ManifestCounts,
+    is_kept,
+    manifest_digest,
+    remove_optional_files,
+};
+use snapper::merkle::{ self, ClaimProof, MerkleRoot, MerkleTree };
+use snapper::metadata::ChainMetadata;
+use snapper::policy::{ DAO_TREASURY, POLICY_FILE, Policy, save_audit };
+use snapper::proof::{ BlockHeader, PROOFS_FILE, PageProof, ProvenTrie };
+use snapper::reconcile::{ ChainTotals, reconcile };
+use snapper::report::{ Report, ReportFormat };
+use snapper::series::{ csv_header, csv_rows, filter_balances, series_blocks };
+use snapper::signature::{ ManifestSignature, load_signatures, save_signatures };
+use snapper::snapshot::{ load_accounts, load_errors, load_stake, snapshot_balances };
+use snapper::source::{
+    decode_account,
+    decode_stake,
+    remove_crawl,
+    resolve_block,
+    snapshot_maps,
+};
+use snapper::{ DumpSource, FixtureSource, LiveSource, NetworkProfile, Snapshot, SnapshotSource };
+
 #[derive(Parser)]
 #[command(version, about, long_about = None)]
 #[command(propagate_version = true)]
@@ -14,339 +55,957 @@ struct CliArgs {
     /// Shows report that includes some interesting info
     #[arg(short = 'r', long)]
     show_report: bool,
+
+    /// Named network profile to use
+    #[arg(short, long, env = "SNAPPER_NETWORK", default_value = "commune")]
+    network: String,
+
+    /// JSON file with network profiles, replaces the built-in profiles
+    #[arg(long, env = "SNAPPER_PROFILES")]
+    profiles: Option<PathBuf>,
+
+    /// Node endpoint, overrides the URL of the selected network profile. May
+    /// be repeated to spread a crawl over several nodes of the same chain.
+    #[arg(short, long, env = "SNAPPER_URL")]
+    url: Vec<String>,
 }
 
 #[derive(Subcommand)]
 enum CliCommands {
     /// Takes a snapshot of the commune chain and aggregates system
     /// account balances with the stake belonging to those accounts.
-    Snap,
+    Snap {
+        /// Block number or 0x-prefixed block hash to take the snapshot at.
+        /// Defaults to the latest finalized block.
+        #[arg(short, long)]
+        block: Option<String>,
+
+        /// Continues an interrupted snapshot from its checkpoint, at the
+        /// checkpoint's block.
+        #[arg(long, conflicts_with = "block")]
+        resume: bool,
+
+        /// Records entries that fail to decode in errors.json instead of
+        /// failing the snapshot.
+        #[arg(long)]
+        lenient: bool,
+
+        /// Consecutive failed requests to retry, with exponential backoff,
+        /// before giving up.
+        #[arg(long, default_value_t = 10)]
+        retries: u32,
+
+        /// Connections crawling key ranges concurrently, spread over the
+        /// `--url` endpoints. Defaults to one per endpoint.
+        #[arg(short = 'j', long)]
+        connections: Option<usize>,
+
+        /// Snapshot directory the output files and the manifest are written to
+        #[arg(short, long, default_value = ".")]
+        out: PathBuf,
+
+        /// Fetches a read proof for every page of entries, checks it against
+        /// the block's state root and keeps it in proofs.jsonl.
+        #[arg(long, conflicts_with = "resume")]
+        proofs: bool,
+
+        /// JSON policy of excluded, remapped and adjusted accounts, applied
+        /// to the aggregated balances. Fired rules are logged to
+        /// policy_audit.json.
+        #[arg(long)]
+        policy: Option<PathBuf>,
+
+        /// Raw state export to read instead of a node: a `state_getPairs`
+        /// result or a raw chain-spec. `--block` then only names the block
+        /// number the export was taken at.
+        #[arg(long, conflicts_with_all = ["resume", "proofs"], requires = "metadata")]
+        dump: Option<PathBuf>,
+
+        /// SCALE encoded runtime metadata to decode `--dump` against, e.g. the
+        /// metadata.scale of an earlier snapshot
+        #[arg(long, requires = "dump")]
+        metadata: Option<PathBuf>,
+
+        /// Holding source to read besides accounts and stake, may be
+        /// repeated. Replaces the sources of the network profile.
+        #[arg(long = "source", value_enum)]
+        sources: Vec<HoldingSource>,
+    },
+    /// Takes snapshots at regular intervals over a block range of an archive
+    /// node and writes the balances into one CSV keyed by (block, address).
+    Series {
+        /// First block of the range
+        #[arg(long)]
+        from: u64,
+
+        /// Last block of the range, included if it falls on the interval
+        #[arg(long)]
+        to: u64,
+
+        /// Number of blocks between two snapshots
+        #[arg(long)]
+        every: u64,
+
+        /// Address to keep, may be repeated. Kept addresses get a row at every
+        /// block, even if they hold nothing.
+        #[arg(short, long = "address")]
+        addresses: Vec<String>,
+
+        /// File with more addresses to keep, one per line
+        #[arg(long)]
+        addresses_file: Option<PathBuf>,
+
+        /// Directory the snapshot of each block is crawled into, one
+        /// subdirectory per block. Finished blocks are reused when the series
+        /// is run again, once their manifest checks out and they read the
+        /// same holding sources.
+        #[arg(short, long, default_value = "series")]
+        work: PathBuf,
+
+        /// CSV file the series is written to
+        #[arg(short, long, default_value = "series.csv")]
+        out: PathBuf,
+
+        /// Records entries that fail to decode instead of failing the series
+        #[arg(long)]
+        lenient: bool,
+
+        /// Consecutive failed requests to retry before giving up
+        #[arg(long, default_value_t = 10)]
+        retries: u32,
+
+        /// Connections crawling key ranges concurrently, spread over the
+        /// `--url` endpoints. Defaults to one per endpoint.
+        #[arg(short = 'j', long)]
+        connections: Option<usize>,
+
+        /// Holding source to read besides accounts and stake, may be
+        /// repeated. Replaces the sources of the network profile.
+        #[arg(long = "source", value_enum)]
+        sources: Vec<HoldingSource>,
+    },
+    /// Compares the state the `--url` endpoints serve at the same block: the
+    /// state roots, the keys of every storage entry a snapshot reads and their
+    /// values. Fails if the endpoints disagree.
+    Crosscheck {
+        /// Block number or 0x-prefixed block hash to compare, resolved on the
+        /// first endpoint. Defaults to its latest finalized block.
+        #[arg(short, long)]
+        block: Option<String>,
+
+        /// Compares this many values per storage entry, spread evenly over
+        /// the keys, instead of all of them
+        #[arg(long)]
+        sample: Option<usize>,
+
+        /// Number of mismatching keys to list
+        #[arg(long, default_value_t = 10)]
+        limit: usize,
+
+        /// JSON file the comparison is written to
+        #[arg(short, long)]
+        out: Option<PathBuf>,
+
+        /// Holding source whose storage is compared too, may be repeated.
+        /// Replaces the sources of the network profile.
+        #[arg(long = "source", value_enum)]
+        sources: Vec<HoldingSource>,
+    },
+    /// Reports on an existing snapshot without connecting to a node.
+    Report {
+        /// Snapshot directory to read accounts.json, stake.json and
+        /// total_balances.json from
+        #[arg(short, long, default_value = ".")]
+        input: PathBuf,
+
+        #[arg(short, long, value_enum, default_value_t)]
+        format: ReportFormat,
+
+        /// Number of largest holders to list
+        #[arg(long, default_value_t = 10)]
+        top: usize,
+    },
+    /// Aggregates the StakeTo entries of a snapshot by target, separating
+    /// self stake from delegated stake, and exports the delegation graph.
+    StakeReport {
+        /// Snapshot directory to read stake.json from
+        #[arg(short, long, default_value = ".")]
+        input: PathBuf,
+
+        #[arg(short, long, value_enum, default_value_t)]
+        format: ReportFormat,
+
+        /// Number of delegates with the most delegated stake to list
+        #[arg(long, default_value_t = 10)]
+        top: usize,
+
+        /// File to export the delegation graph to
+        #[arg(short, long)]
+        graph: Option<PathBuf>,
+
+        #[arg(long, value_enum, default_value_t)]
+        graph_format: GraphFormat,
+    },
+    /// Compares a snapshot with the totals the chain reported at the same
+    /// block and fails if they differ by more than the tolerance.
+    Reconcile {
+        /// Snapshot directory holding totals.json and the balance files
+        #[arg(short, long, default_value = ".")]
+        input: PathBuf,
+
+        /// Largest accepted discrepancy relative to the on-chain total, in percent
+        #[arg(short, long, default_value_t = 0.0)]
+        tolerance: f64,
+    },
+    /// Lists what moved between two snapshots: accounts that appeared or
+    /// disappeared, balance changes and stake re-delegations.
+    Diff {
+        /// Earlier snapshot directory
+        a: PathBuf,
+
+        /// Later snapshot directory
+        b: PathBuf,
+
+        /// Smallest balance or stake change to list, in the chain's base unit
+        #[arg(short, long, default_value_t = 0)]
+        threshold: u128,
+
+        /// CSV file to write every change to
+        #[arg(short, long, default_value = "diff.csv")]
+        out: PathBuf,
+    },
+    /// Signs the manifest of a snapshot directory with an sr25519 key and adds
+    /// the signature to signatures.json.
+    Sign {
+        /// Snapshot directory whose manifest is signed
+        #[arg(short, long, default_value = ".")]
+        input: PathBuf,
+
+        /// Secret URI of the signing key, e.g. a mnemonic or `//Alice`
+        #[arg(short, long, env = "SNAPPER_KEY", hide_env_values = true)]
+        key: String,
+    },
+    /// Checks the manifest and its signatures, requiring valid signatures of
+    /// allowed signers.
+    Verify {
+        /// Snapshot directory to verify
+        #[arg(short, long, default_value = ".")]
+        input: PathBuf,
+
+        /// SS58 address whose signature is accepted, may be repeated
+        #[arg(short, long = "signer", required = true)]
+        signers: Vec<String>,
+
+        /// Number of distinct allowed signers that must have signed
+        #[arg(long, default_value_t = 1)]
+        min_signatures: usize,
+    },
+    /// Re-checks a snapshot taken with `--proofs` offline: the header against
+    /// the block hash, the proofs against the state root, that the maps hold
+    /// no keys besides the proven ones and the decoded accounts and stake
+    /// against the proven values.
+    CheckProofs {
+        /// Snapshot directory to check
+        #[arg(short, long, default_value = ".")]
+        input: PathBuf,
+    },
+    /// Turns a snapshot into the balances section of a mod-chain
+    /// chain-spec, or patches an existing chain-spec with it.
+    Genesis {
+        /// Snapshot directory to read total_balances.json from
+        #[arg(short, long, default_value = ".")]
+        input: PathBuf,
+
+        /// Network profile of the chain the genesis is built for
+        #[arg(short, long, default_value = "modchain")]
+        target: String,
+
+        /// Plain chain-spec JSON whose balances are replaced
+        #[arg(long)]
+        chain_spec: Option<PathBuf>,
+
+        /// File the balances section or the patched chain-spec is written to
+        #[arg(short, long, default_value = "genesis.json")]
+        out: PathBuf,
+
+        /// Fails unless the genesis balances add up to exactly this amount,
+        /// in the target's smallest unit
+        #[arg(long)]
+        expected_issuance: Option<u128>,
+
+        /// What to do with balances below the target's existential deposit:
+        /// drop-burn, sweep:<address> or round-up. `sweep:dao-treasury`
+        /// sweeps to the DAO treasury recorded in the snapshot.
+        #[arg(long, default_value = "drop-burn")]
+        dust: DustStrategy,
+
+        /// JSON file the effect of the dust strategy is written to
+        #[arg(long)]
+        dust_report: Option<PathBuf>,
+    },
+    /// Builds a Merkle tree over the (account, amount) claims of a
+    /// snapshot and writes its root and a proof file per address.
+    Merkle {
+        /// Snapshot directory to read total_balances.json from
+        #[arg(short, long, default_value = ".")]
+        input: PathBuf,
+
+        /// Network profile of the chain the claims are made on
+        #[arg(short, long, default_value = "modchain")]
+        target: String,
+
+        /// Directory the root and the proofs are written to
+        #[arg(short, long, default_value = "merkle")]
+        out: PathBuf,
+
+        /// What to do with balances below the target's existential deposit:
+        /// drop-burn, sweep:<address> or round-up. `sweep:dao-treasury`
+        /// sweeps to the DAO treasury recorded in the snapshot.
+        #[arg(long, default_value = "drop-burn")]
+        dust: DustStrategy,
+
+        /// JSON file the effect of the dust strategy is written to
+        #[arg(long)]
+        dust_report: Option<PathBuf>,
+    },
+    /// Checks a claim proof file against a published Merkle root, offline.
+    VerifyProof {
+        /// Hex encoded Merkle root
+        #[arg(long)]
+        root: String,
+
+        /// Proof file written by `snapper merkle`
+        proof: PathBuf,
+    },
 }
 
-use crate::chain::runtime_types::{
-    frame_system::AccountInfo as ChainAccountInfo,
-    pallet_balances::types::AccountData as ChainAccountData,
-};
-
-#[subxt::subxt(runtime_metadata_path = "./metadata.commune.scale")]
-pub mod chain {}
-
-#[derive(Serialize, Deserialize, Debug)]
-pub struct AccountData {
-    pub free: u64,
-    pub reserved: u64,
-    pub frozen: u64,
-    pub flags: u128,
-}
-
-#[derive(Serialize, Deserialize, Debug)]
-pub struct Account {
-    pub nonce: u32,
-    pub consumers: u32,
-    pub providers: u32,
-    pub sufficients: u32,
-    pub data: AccountData,
-}
-
-impl From<ChainAccountInfo<u32, ChainAccountData<u64>>> for Account {
-    fn from(value: ChainAccountInfo<u32, ChainAccountData<u64>>) -> Self {
-        Self {
-            nonce: value.nonce,
-            consumers: value.consumers,
-            providers: value.providers,
-            sufficients: value.sufficients,
-            data: AccountData {
-                free: value.data.free,
-                reserved: value.data.reserved,
-                frozen: value.data.frozen,
-                flags: value.data.flags.0,
-            },
+/// Replaces a sweep to the DAO treasury alias with the treasury address
+/// recorded in the snapshot's totals.
+async fn resolve_dust_strategy(dir: &Path, strategy: DustStrategy) -> Result<DustStrategy> {
+    match strategy {
+        DustStrategy::Sweep(address) if address == DAO_TREASURY => {
+            let treasury = ChainTotals::load(dir).await?.dao_treasury.ok_or_else(||
+                anyhow!("Snapshot {} has no DAO treasury to sweep to", dir.display())
+            )?;
+            Ok(DustStrategy::Sweep(treasury))
         }
+        strategy => Ok(strategy),
     }
 }
 
-pub async fn iter(api: &OnlineClient<SubstrateConfig>) -> Result<Vec<(String, Account)>> {
-    let mut accounts: Vec<(String, Account)> = Vec::new();
-    let storage_query = subxt::dynamic::storage("System", "Account", vec![]);
-    let mut results = api.storage().at_latest().await?.iter(storage_query).await?;
-
-    let mut idx = 0;
-
-    while let Some(Ok(kv)) = results.next().await {
-        let mut address = String::new();
-        let key = kv.keys.as_slice()[0].clone();
-        let key_value = key.value;
-        if let scale_value::ValueDef::Composite(key_value_values) = &key_value {
-            if let scale_value::Composite::Unnamed(unnamed_composite) = key_value_values {
-                let first_value = unnamed_composite.first().unwrap();
-                if let scale_value::ValueDef::Composite(first_vv) = &first_value.value {
-                    if let scale_value::Composite::Unnamed(finally) = first_vv {
-                        let kb = finally
-                            .iter()
-                            .map(|v| v.as_u128().unwrap_or(0) as u8)
-                            .collect::<Vec<u8>>();
-                        // Ensure kb has at least 32 bytes before slicing
-                        if kb.len() >= 32 {
-                            // Copy the first 32 bytes into a fixed-size array
-                            let mut kb_fixed = [0u8; 32];
-                            kb_fixed.copy_from_slice(&kb[0..32]);
-                            address = AccountId32::from(kb_fixed).to_string();
-                        } else {
-                            println!("kb length is less than 32 bytes, cannot create AccountId32");
-                        }
-                    }
-                }
-            }
+/// Writes the manifest of a saved snapshot and the balances it covers.
+async fn save_manifest(
+    dir: &Path,
+    snapshot: &Snapshot,
+    balances: &BTreeMap<String, Balance>
+) -> Result<Manifest> {
+    let manifest = Manifest::new(
+        dir,
+        &snapshot.source,
+        &snapshot.endpoints,
+        snapshot.block.clone(),
+        snapshot.spec_version,
+        ManifestCounts {
+            accounts: snapshot.accounts.len() as u64,
+            stake: snapshot.stake.len() as u64,
+            failed: snapshot.errors.len() as u64,
+            balances: balances.len() as u64,
         }
+    ).await?;
+    manifest.save(dir).await?;
 
-        let raw_account: ChainAccountInfo<u32, ChainAccountData<u64>> = kv.value.as_type()?;
-        let account: Account = raw_account.into();
-        idx += 1;
-        println!("#{}:\t{}\tfree: {}", idx, &address, &account.data.free);
-        accounts.push((address, account));
-    }
-    Ok(accounts)
+    Ok(manifest)
 }
 
-pub async fn stake_to(api: &OnlineClient<SubstrateConfig>) -> Result<Vec<(String, String, u128)>> {
-    let mut stake_to: Vec<(String, String, u128)> = Vec::new();
-    let storage_query = subxt::dynamic::storage("SubspaceModule", "StakeTo", vec![]);
-    let mut results = api.storage().at_latest().await?.iter(storage_query).await?;
-
-    let mut idx = 0;
-
-    while let Some(Ok(kv)) = results.next().await {
-        let raw_key_from = kv.keys.as_slice()[0].clone();
-        let mut key_from = String::new();
-
-        if
-            let scale_value::ValueDef::Composite(scale_value::Composite::Unnamed(rkf_map)) =
-                raw_key_from.value
-        {
-            let address_map = rkf_map.first().unwrap();
-            if
-                let scale_value::ValueDef::Composite(
-                    scale_value::Composite::Unnamed(address_map_inner),
-                ) = &address_map.value
-            {
-                let address_bytes = address_map_inner
-                    .iter()
-                    .map(|v| v.as_u128().unwrap_or(0) as u8)
-                    .collect::<Vec<u8>>();
-                if address_bytes.len() >= 32 {
-                    let mut address_bytes_fixed = [0u8; 32];
-                    address_bytes_fixed.copy_from_slice(&address_bytes[0..32]);
-                    key_from = AccountId32::from(address_bytes_fixed).to_string();
-                } else {
-                    println!("Address length is less than 32 bytes, cannot create AccountId32");
-                }
-            }
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    let cli_args = CliArgs::parse();
+    let mut profile = NetworkProfile::load(cli_args.profiles.as_deref(), &cli_args.network)?;
+    let urls = match cli_args.url.first() {
+        Some(url) => {
+            profile.url = url.clone();
+            cli_args.url
         }
+        None => vec![profile.url.clone()],
+    };
 
-        let raw_key_to = kv.keys.as_slice()[1].clone();
-        let mut key_to = String::new();
-
-        if
-            let scale_value::ValueDef::Composite(scale_value::Composite::Unnamed(rkt_map)) =
-                raw_key_to.value
-        {
-            let address_map = rkt_map.first().unwrap();
-            if
-                let scale_value::ValueDef::Composite(
-                    scale_value::Composite::Unnamed(address_map_inner),
-                ) = &address_map.value
-            {
-                let address_bytes = address_map_inner
-                    .iter()
-                    .map(|v| v.as_u128().unwrap_or(0) as u8)
-                    .collect::<Vec<u8>>();
-                if address_bytes.len() >= 32 {
-                    let mut address_bytes_fixed = [0u8; 32];
-                    address_bytes_fixed.copy_from_slice(&address_bytes[0..32]);
-                    key_to = AccountId32::from(address_bytes_fixed).to_string();
-                } else {
-                    println!("Address length is less than 32 bytes, cannot create AccountId32");
+    match cli_args.command {
+        CliCommands::Snap {
+            block,
+            resume,
+            lenient,
+            retries,
+            connections,
+            out,
+            proofs,
+            policy,
+            dump,
+            metadata,
+            sources,
+        } => {
+            if !sources.is_empty() {
+                profile.sources = sources;
+            }
+            tokio::fs::create_dir_all(&out).await?;
+            let policy = match &policy {
+                Some(path) => Some((path, Policy::load(path).await?)),
+                None => None,
+            };
+            // A resumed snapshot keeps what its first run wrote, e.g. header.json
+            if !resume {
+                let policy = policy.as_ref().map(|(path, _)| *path);
+                let keep = [policy, dump.as_ref(), metadata.as_ref()]
+                    .into_iter()
+                    .flatten()
+                    .map(PathBuf::as_path)
+                    .collect::<Vec<&Path>>();
+                remove_optional_files(&out, &keep).await?;
+            }
+            let live = dump.is_none();
+            let snapshot = match dump {
+                Some(dump) => {
+                    let metadata = metadata.expect("clap requires --metadata with --dump");
+                    let number = match block {
+                        Some(block) => block.parse().map_err(|_|
+                            anyhow!("Snapshots of a state dump need a block number, not {}", block)
+                        )?,
+                        None => 0,
+                    };
+                    let mut source = DumpSource::load(&dump, &metadata, number).await?;
+                    source.snapshot(&profile, lenient).await?
+                }
+                None => {
+                    let connections = connections.unwrap_or(urls.len());
+                    let mut clients = Client::connect_all(&urls, connections, retries).await?;
+                    let mut source = LiveSource {
+                        clients: &mut clients,
+                        block,
+                        dir: out.clone(),
+                        resume,
+                        proofs,
+                    };
+                    source.snapshot(&profile, lenient).await?
+                }
+            };
+            snapshot.save(&out).await?;
+
+            let mut balances = snapshot.balances();
+            if let Some((path, policy)) = policy {
+                let dao_treasury = snapshot.totals.dao_treasury.as_deref();
+                let audit = policy.apply(&mut balances, dao_treasury, &profile)?;
+                println!("Policy rules changed {} balances, see policy_audit.json", audit.len());
+                save_audit(&out, &audit).await?;
+                // The policy may already be the snapshot's policy.json, e.g. with `--out .`
+                if !is_kept(&out.join(POLICY_FILE), &[path]).await {
+                    let json = serde_json::to_string_pretty(&policy)?;
+                    tokio::fs::write(out.join(POLICY_FILE), json).await?;
                 }
             }
-        }
+            save_balances(&out, &balances).await?;
 
-        let mut staked: u128 = 0;
-        if let Ok(v) = kv.value.to_value() {
-            if let Some(s) = v.as_u128() {
-                staked = s;
+            let manifest = save_manifest(&out, &snapshot, &balances).await?;
+            // Only now that everything is written the crawl is no longer needed
+            if live {
+                remove_crawl(&out, &profile.sources).await?;
             }
-        }
-
-        println!("#{}:", idx);
-        println!("\tF:\t{}", key_from);
-        println!("\tT:\t{}", key_to);
-        println!("\tS:\t{}", staked);
-
-        idx += 1;
-        stake_to.push((key_from, key_to, staked));
-    }
-
-    Ok(stake_to)
-}
-
-async fn fetch_accounts(api: &OnlineClient<SubstrateConfig>) -> Result<()> {
-    let accounts = iter(&api).await?;
-
-    // Save the accounts Vec<Account> to a JSON file called "accounts.json"
-    let json = serde_json::to_string_pretty(&accounts)?;
-    tokio::fs::write("accounts.json", json).await?;
-
-    Ok(())
-}
+            println!("Snapshot written to {}", out.display());
 
-async fn fetch_stake(api: &OnlineClient<SubstrateConfig>) -> Result<()> {
-    let stake = stake_to(&api).await?;
-
-    let json = serde_json::to_string_pretty(&stake)?;
-    tokio::fs::write("stake.json", json).await?;
+            if cli_args.show_report {
+                let report = Report::new(
+                    &balances,
+                    &snapshot.holdings.unattributed,
+                    snapshot.accounts.len(),
+                    snapshot.stake.len(),
+                    manifest.block,
+                    &profile,
+                    10
+                );
+                print!("{}", report.render(ReportFormat::Text, &profile));
+            }
 
-    Ok(())
-}
+            Ok(())
+        }
+        CliCommands::Series {
+            from,
+            to,
+            every,
+            addresses,
+            addresses_file,
+            work,
+            out,
+            lenient,
+            retries,
+            connections,
+            sources,
+        } => {
+            if !sources.is_empty() {
+                profile.sources = sources;
+            }
+            let blocks = series_blocks(from, to, every)?;
+            let mut filter = addresses;
+            if let Some(path) = addresses_file {
+                let list = tokio::fs::read_to_string(&path).await?;
+                let listed = list.lines().map(str::trim).filter(|line| !line.is_empty());
+                filter.extend(listed.map(String::from));
+            }
+            // Normalise to the prefix the balances are keyed by
+            let filter = filter
+                .iter()
+                .map(|address| Ok(profile.encode_address(NetworkProfile::decode_address(address)?)))
+                .collect::<Result<BTreeSet<String>>>()?;
+
+            let connections = connections.unwrap_or(urls.len());
+            let mut clients = Client::connect_all(&urls, connections, retries).await?;
+            tokio::fs::write(&out, csv_header()).await?;
+            for (idx, number) in blocks.iter().enumerate() {
+                println!("Block #{} ({}/{})", number, idx + 1, blocks.len());
+                let dir = work.join(number.to_string());
+                let interrupted = dir.join(CHECKPOINT_FILE).exists();
+                // Blocks taken earlier are reused if they read the same holding sources
+                let mut reuse = false;
+                if !interrupted && dir.join(MANIFEST_FILE).exists() {
+                    Manifest::verify(&dir).await?;
+                    reuse = Holdings::load(&dir).await?.sources == profile.sources;
+                    if !reuse {
+                        println!("Block #{} was taken with other holding sources", number);
+                        remove_optional_files(&dir, &[]).await?;
+                    }
+                }
+                let snapshot = if reuse {
+                    FixtureSource { dir }.snapshot(&profile, lenient).await?
+                } else {
+                    let mut source = LiveSource {
+                        clients: &mut clients,
+                        block: Some(number.to_string()),
+                        dir: dir.clone(),
+                        resume: interrupted,
+                        proofs: false,
+                    };
+                    let snapshot = source.snapshot(&profile, lenient).await?;
+                    snapshot.save(&dir).await?;
+                    let balances = snapshot.balances();
+                    save_balances(&dir, &balances).await?;
+                    save_manifest(&dir, &snapshot, &balances).await?;
+                    remove_crawl(&dir, &profile.sources).await?;
+                    snapshot
+                };
+                let balances = filter_balances(snapshot.balances(), &filter);
+                append_partial(&out, &csv_rows(*number, &balances)).await?;
+            }
+            println!("Wrote {} blocks to {}", blocks.len(), out.display());
 
-async fn parse_accounts() -> Result<Vec<(String, Account)>> {
-    let accounts_data = tokio::fs::read_to_string("accounts.json").await?;
-    let accounts_json: serde_json::Value = serde_json::from_str(&accounts_data)?;
-    // Convert accounts_json (serde_json::Value) into Vec<(String, Account)>
-    let accounts_vec: Vec<(String, Account)> = accounts_json
-        .as_array()
-        .expect("accounts_json should be an array")
-        .iter()
-        .map(|item| {
-            let arr = item.as_array().expect("each item should be an array");
-            let address = arr[0].as_str().expect("first item is address string").to_string();
-            let account_obj = &arr[1];
-            let account: Account = serde_json
-                ::from_value(account_obj.clone())
-                .expect("should parse to Account");
-            (address, account)
-        })
-        .collect();
-
-    Ok(accounts_vec)
-}
+            Ok(())
+        }
+        CliCommands::Crosscheck { block, sample, limit, out, sources } => {
+            if !sources.is_empty() {
+                profile.sources = sources;
+            }
+            if urls.len() < 2 {
+                bail!("crosscheck needs at least two endpoints, given with --url");
+            }
+            let clients = Client::connect_all(&urls, urls.len(), 0).await?;
+            let (_, block) = resolve_block(&clients[0], block).await?;
+
+            let maps = snapshot_maps(&profile.sources);
+            let crosscheck = Crosscheck::run(&clients, block, &maps, sample, limit).await?;
+            print!("{}", crosscheck.render_text());
+            if let Some(path) = out {
+                tokio::fs::write(&path, serde_json::to_string_pretty(&crosscheck)?).await?;
+                println!("Wrote {}", path.display());
+            }
+            if !crosscheck.agrees() {
+                bail!("Endpoints disagree at block #{}", crosscheck.block.number);
+            }
 
-async fn parse_stake() -> Result<Vec<(String, String, u128)>> {
-    let stake_data = tokio::fs::read_to_string("stake.json").await?;
-    let stake_json: serde_json::Value = serde_json::from_str(&stake_data)?;
-    let stake_vec: Vec<(String, String, u128)> = stake_json
-        .as_array()
-        .expect("stake_json should be an array")
-        .iter()
-        .map(|item| {
-            let arr = item.as_array().expect("each item should be an array");
-            let from = arr[0].as_str().expect("first item is string").to_string();
-            let to = arr[1].as_str().expect("second item is string").to_string();
-            let staked = arr[2]
-                .as_u64()
-                .map(|v| v as u128)
-                .expect("third item should be u128-compatible");
-            (from, to, staked)
-        })
-        .collect();
-
-    Ok(stake_vec)
-}
+            Ok(())
+        }
+        CliCommands::Report { input, format, top } => {
+            let manifest = Manifest::verify(&input).await?;
+            let accounts = load_accounts(&input).await?;
+            let stake = load_stake(&input).await?;
+            let holdings = Holdings::load(&input).await?;
+            let balances = if input.join("total_balances.json").exists() {
+                load_balances(&input).await?
+            } else {
+                map_balances(&accounts, &stake, &holdings.holdings)
+            };
+
+            let report = Report::new(
+                &balances,
+                &holdings.unattributed,
+                accounts.len(),
+                stake.len(),
+                manifest.block,
+                &profile,
+                top
+            );
+            print!("{}", report.render(format, &profile));
+
+            Ok(())
+        }
+        CliCommands::StakeReport { input, format, top, graph, graph_format } => {
+            let manifest = Manifest::verify(&input).await?;
+            let stake = load_stake(&input).await?;
+
+            let report = StakeReport::new(&stake, manifest.block, top);
+            print!("{}", report.render(format, &profile));
+
+            if let Some(path) = graph {
+                let graph = match graph_format {
+                    GraphFormat::Csv => delegation::to_csv(&stake),
+                    GraphFormat::Graphml => delegation::to_graphml(&stake),
+                };
+                tokio::fs::write(&path, graph).await?;
+                println!("Wrote {}", path.display());
+            }
 
-async fn map_balances(
-    accounts: Vec<(String, Account)>,
-    stake: Vec<(String, String, u128)>
-) -> HashMap<String, u128> {
-    let mut balance_sum_map: HashMap<String, u128> = HashMap::new();
+            Ok(())
+        }
+        CliCommands::Reconcile { input, tolerance } => {
+            Manifest::verify(&input).await?;
+            // Compare what was crawled, before any policy changed the balances.
+            // Holdings are left out, the chain totals do not cover them.
+            let balances = map_balances(
+                &load_accounts(&input).await?,
+                &load_stake(&input).await?,
+                &[]
+            );
+            let totals = ChainTotals::load(&input).await?;
+
+            let discrepancies = reconcile(&totals, &balances);
+            let mut exceeded = 0;
+            for discrepancy in &discrepancies {
+                let ok = !discrepancy.exceeds(tolerance);
+                if !ok {
+                    exceeded += 1;
+                }
+                println!(
+                    "{}: chain {} snapshot {} off by {} ({:.6}%) {}",
+                    discrepancy.name,
+                    profile.bal(discrepancy.chain),
+                    profile.bal(discrepancy.snapshot),
+                    profile.bal(discrepancy.absolute),
+                    discrepancy.relative,
+                    if ok { "ok" } else { "EXCEEDED" }
+                );
+            }
+            if exceeded > 0 {
+                bail!(
+                    "{} of {} totals exceed the tolerance of {}%",
+                    exceeded,
+                    discrepancies.len(),
+                    tolerance
+                );
+            }
 
-    for (from, _to, staked) in &stake {
-        let entry = balance_sum_map.entry(from.clone()).or_insert(0u128);
-        *entry += staked;
-    }
-    println!("{} final stake entries compared to {}", balance_sum_map.len(), stake.len());
+            Ok(())
+        }
+        CliCommands::Diff { a, b, threshold, out } => {
+            Manifest::verify(&a).await?;
+            Manifest::verify(&b).await?;
+            let mut changes = diff_balances(
+                &snapshot_balances(&a).await?,
+                &snapshot_balances(&b).await?,
+                threshold
+            );
+            changes.extend(
+                diff_stake(&load_stake(&a).await?, &load_stake(&b).await?, threshold)
+            );
+
+            tokio::fs::write(&out, diff::to_csv(&changes)).await?;
+            print!("{}", diff::summary(&changes, &profile));
+            println!("{} changes written to {}", changes.len(), out.display());
+
+            Ok(())
+        }
+        CliCommands::Sign { input, key } => {
+            Manifest::verify(&input).await?;
+            let pair = sr25519::Pair
+                ::from_string(&key, None)
+                .map_err(|err| anyhow!("Invalid signing key: {:?}", err))?;
+            let digest = manifest_digest(&input).await?;
+
+            let signature = ManifestSignature::sign(&pair, &digest, &profile);
+            let mut signatures = load_signatures(&input).await?;
+            signatures.retain(|existing| existing.signer != signature.signer);
+            signatures.push(signature.clone());
+            save_signatures(&input, &signatures).await?;
+            println!("Signed manifest digest 0x{} as {}", hex::encode(digest), signature.signer);
+
+            Ok(())
+        }
+        CliCommands::Verify { input, signers, min_signatures } => {
+            let manifest = Manifest::verify(&input).await?;
+            println!(
+                "Files match the manifest of block #{} ({})",
+                manifest.block.number,
+                manifest.block.hash
+            );
+            let allowed = signers
+                .iter()
+                .map(|signer| NetworkProfile::decode_address(signer))
+                .collect::<Result<Vec<_>>>()?;
+            let digest = manifest_digest(&input).await?;
+
+            let mut valid = BTreeSet::new();
+            // signatures.json is not covered by the manifest, so signatures of
+            // anyone else are ignored, whether they are valid or not
+            for signature in load_signatures(&input).await? {
+                let Ok(account) = NetworkProfile::decode_address(&signature.signer) else {
+                    println!("Ignoring signature of invalid address {}", signature.signer);
+                    continue;
+                };
+                if !allowed.contains(&account) {
+                    println!("Ignoring signature of {}, not an allowed signer", signature.signer);
+                    continue;
+                }
+                if !signature.verify(&digest)? {
+                    bail!("Invalid signature of {}", signature.signer);
+                }
+                println!("Valid signature of {}", signature.signer);
+                valid.insert(account);
+            }
+            if valid.len() < min_signatures {
+                bail!(
+                    "{} of the required {} allowed signers signed the manifest",
+                    valid.len(),
+                    min_signatures
+                );
+            }
 
-    for (address, account) in &accounts {
-        let entry = balance_sum_map.entry(address.clone()).or_insert(0u128);
-        *entry += account.data.free as u128;
-        *entry += account.data.reserved as u128;
-        *entry += account.data.frozen as u128;
-    }
-    println!("{} final balance entries compared to {}", balance_sum_map.len(), accounts.len());
+            Ok(())
+        }
+        CliCommands::CheckProofs { input } => {
+            let manifest = Manifest::verify(&input).await?;
+            if !manifest.files.contains_key(PROOFS_FILE) {
+                bail!("{} was taken without --proofs", input.display());
+            }
+            let header = BlockHeader::load(&input).await?;
+            let state_root = header.verify()?;
+            if header.hash != manifest.block.hash {
+                bail!("header.json is not the header of block {}", manifest.block.hash);
+            }
+            let raw_metadata = tokio::fs::read(input.join("metadata.scale")).await?;
+            let metadata = ChainMetadata::decode(&raw_metadata)?;
+
+            let holdings = Holdings::load(&input).await?;
+            let prefixes = snapshot_maps(&holdings.sources)
+                .into_iter()
+                .map(|(pallet, entry)| {
+                    (format!("{}.{}", pallet, entry), storage_prefix(pallet, entry))
+                })
+                .collect::<BTreeMap<String, Vec<u8>>>();
+
+            let mut accounts = Vec::new();
+            let mut stake = Vec::new();
+            let mut raw = RawEntries::new();
+            let mut proven = BTreeMap::<String, BTreeSet<Vec<u8>>>::new();
+            let mut failed = Vec::new();
+            let pages: Vec<PageProof> = read_partial(&input.join(PROOFS_FILE)).await?;
+            for page in &pages {
+                let prefix = prefixes
+                    .get(&page.map)
+                    .ok_or_else(|| anyhow!("Unexpected map {} in {}", page.map, PROOFS_FILE))?;
+                for (key, value) in page.check(&state_root, prefix)? {
+                    proven.entry(page.map.clone()).or_default().insert(key.clone());
+                    let decoded = match page.map.as_str() {
+                        "System.Account" => decode_account(&metadata, &profile, &key, &value)
+                            .map(|account| accounts.push(account)),
+                        "SubspaceModule.StakeTo" => decode_stake(&metadata, &profile, &key, &value)
+                            .map(|edge| stake.push(edge)),
+                        map => {
+                            let entries = raw.entry(map.to_string()).or_default();
+                            entries.push((key.clone(), value.clone()));
+                            Ok(())
+                        }
+                    };
+                    if decoded.is_err() {
+                        failed.push((page.map.clone(), hex::encode(&key), hex::encode(&value)));
+                    }
+                }
+            }
+            // The maps must hold no keys besides the proven ones
+            let trie = ProvenTrie::new(&state_root, &pages)?;
+            for (pallet, entry) in snapshot_maps(&holdings.sources) {
+                let name = format!("{}.{}", pallet, entry);
+                let keys = trie
+                    .keys(&storage_prefix(pallet, entry))?
+                    .into_iter()
+                    .collect::<BTreeSet<Vec<u8>>>();
+                let read = proven.remove(&name).unwrap_or_default();
+                if keys != read {
+                    let (held, read) = (keys.len(), read.len());
+                    bail!("{} keys read of {} differ from the {} keys it holds", read, name, held);
+                }
+            }
 
-    balance_sum_map
-}
+            let mut errors = Vec::new();
+            let proven = collect_holdings(
+                &metadata,
+                &profile,
+                &holdings.sources,
+                &stake,
+                &raw,
+                true,
+                &mut errors
+            )?;
+            failed.extend(errors.into_iter().map(|error| (error.map, error.key, error.value)));
+            println!(
+                "{} read proofs lead to the state root {} of block #{}",
+                pages.len(),
+                header.state_root,
+                header.number
+            );
 
-async fn save_balances(balances: HashMap<String, u128>) -> Result<()> {
-    let json = serde_json::to_string_pretty(&balances)?;
-    tokio::fs::write("total_balances.json", json).await?;
+            if
+                serde_json::to_value(&accounts)? !=
+                serde_json::to_value(load_accounts(&input).await?)?
+            {
+                bail!("accounts.json differs from the proven System.Account entries");
+            }
+            if stake != load_stake(&input).await? {
+                bail!("stake.json differs from the proven SubspaceModule.StakeTo entries");
+            }
+            if proven != holdings {
+                bail!("{} differs from the holdings of the proven storage", HOLDINGS_FILE);
+            }
+            // The entries that fail to decode must be the ones errors.json records
+            let recorded = load_errors(&input).await?
+                .into_iter()
+                .map(|error| (error.map, error.key, error.value))
+                .collect::<BTreeSet<_>>();
+            if failed.into_iter().collect::<BTreeSet<_>>() != recorded {
+                bail!("errors.json differs from the proven entries that fail to decode");
+            }
+            println!(
+                "{} accounts, {} stake entries and {} holdings match the proven storage",
+                accounts.len(),
+                stake.len(),
+                proven.holdings.len()
+            );
+
+            Ok(())
+        }
+        CliCommands::Genesis {
+            input,
+            target,
+            chain_spec,
+            out,
+            expected_issuance,
+            dust,
+            dust_report,
+        } => {
+            let target = NetworkProfile::load(cli_args.profiles.as_deref(), &target)?;
+            Manifest::verify(&input).await?;
+            let balances = load_balances(&input).await?;
+            let dust = resolve_dust_strategy(&input, dust).await?;
+            let genesis = genesis_balances(&balances, &profile, &target, &dust)?;
+            println!(
+                "{} genesis balances totalling {}",
+                genesis.balances.len(),
+                target.bal(genesis.total)
+            );
+            println!("{}", genesis.dust.render(&target));
+            if let Some(path) = dust_report {
+                tokio::fs::write(&path, serde_json::to_string_pretty(&genesis.dust)?).await?;
+            }
 
-    Ok(())
-}
+            if let Some(expected) = expected_issuance && expected != genesis.total {
+                bail!(
+                    "Genesis balances total {} but {} was expected (off by {})",
+                    genesis.total,
+                    expected,
+                    expected.abs_diff(genesis.total)
+                );
+            }
 
-#[tokio::main]
-async fn main() -> anyhow::Result<()> {
-    let cli_args = CliArgs::parse();
-    let api = OnlineClient::<SubstrateConfig>::from_url(
-        "wss://commune-archive-node-0.communeai.net"
-    ).await?;
+            let output = match chain_spec {
+                Some(path) => {
+                    let mut spec: serde_json::Value = serde_json::from_str(
+                        &tokio::fs::read_to_string(&path).await?
+                    )?;
+                    let replaced = patch_chain_spec(&mut spec, &genesis.balances)?;
+                    println!("Replaced {} balances of {}", replaced, path.display());
+                    spec
+                }
+                None => serde_json::json!({ "balances": { "balances": genesis.balances } }),
+            };
+            tokio::fs::write(&out, serde_json::to_string_pretty(&output)?).await?;
+            println!("Wrote {}", out.display());
 
-    match cli_args.command {
-        CliCommands::Snap => {
-            fetch_accounts(&api).await?;
-            fetch_stake(&api).await?;
-            let accounts = parse_accounts().await?;
-            let stake = parse_stake().await?;
-            let balances = map_balances(accounts, stake).await;
-            save_balances(balances.clone()).await?;
+            Ok(())
+        }
+        CliCommands::Merkle { input, target, out, dust, dust_report } => {
+            let target = NetworkProfile::load(cli_args.profiles.as_deref(), &target)?;
+            Manifest::verify(&input).await?;
+            let balances = load_balances(&input).await?;
+            let dust = resolve_dust_strategy(&input, dust).await?;
+            let genesis = genesis_balances(&balances, &profile, &target, &dust)?;
+            println!("{}", genesis.dust.render(&target));
+            if let Some(path) = dust_report {
+                tokio::fs::write(&path, serde_json::to_string_pretty(&genesis.dust)?).await?;
+            }
 
-            if cli_args.show_report {
-                let total_issuance = balances
-                    .iter()
-                    .map(|(_, &b)| b)
-                    .reduce(|a, b| a + b)
-                    .unwrap();
-                println!("Total Issuance: {}", bal(total_issuance));
-
-                const EXISTENTIAL_DEPOSIT: u128 = 500;
-                let mut nonexistent_accounts: Vec<(String, u128)> = Vec::new();
-                for (account, total_balance) in &balances {
-                    if *total_balance < EXISTENTIAL_DEPOSIT {
-                        nonexistent_accounts.push((account.clone(), total_balance.clone()));
-                    }
-                }
-                let nonexistent_total = nonexistent_accounts
-                    .iter()
-                    .map(|a| a.1)
-                    .reduce(|acc, b| acc + b)
-                    .unwrap();
+            let mut leaves = Vec::new();
+            for (address, amount) in &genesis.balances {
+                leaves.push(merkle::encode_leaf(NetworkProfile::decode_address(address)?, *amount));
+            }
+            let tree = MerkleTree::new(&leaves)?;
+
+            tokio::fs::create_dir_all(out.join("proofs")).await?;
+            let claims = genesis.balances.iter().zip(&leaves);
+            for (idx, ((address, amount), leaf)) in claims.enumerate() {
+                let proof = ClaimProof {
+                    address: address.clone(),
+                    amount: *amount,
+                    leaf: merkle::to_hex(leaf),
+                    proof: tree
+                        .proof(idx)
+                        .iter()
+                        .map(|hash| merkle::to_hex(hash))
+                        .collect(),
+                };
+                let json = serde_json::to_string_pretty(&proof)?;
+                tokio::fs::write(out.join("proofs").join(format!("{}.json", address)), json).await?;
+            }
 
-                println!(
-                    "{} nonexistent accounts totalling {}",
-                    nonexistent_accounts.len(),
-                    bal(nonexistent_total)
-                );
+            let root = MerkleRoot {
+                root: merkle::to_hex(&tree.root()),
+                leaves: leaves.len(),
+                total: genesis.total,
+            };
+            tokio::fs::write(out.join("root.json"), serde_json::to_string_pretty(&root)?).await?;
+            println!(
+                "Merkle root {} over {} claims totalling {}",
+                root.root,
+                root.leaves,
+                target.bal(root.total)
+            );
+
+            Ok(())
+        }
+        CliCommands::VerifyProof { root, proof } => {
+            let root = merkle::hash_from_hex(&root)?;
+            let claim: ClaimProof = serde_json::from_str(
+                &tokio::fs::read_to_string(&proof).await?
+            )?;
+            let leaf = merkle::from_hex(&claim.leaf)?;
+            let (account, amount) = merkle::decode_leaf(&leaf)?;
+            let claimant = NetworkProfile::decode_address(&claim.address)?;
+            if account != claimant || amount != claim.amount {
+                bail!("Leaf does not encode the claim of {} for {}", claim.address, claim.amount);
+            }
 
-                // Convert the balances into a vector of tuples and sort descending by value
-                let mut sorted_balances: Vec<(&String, &u128)> = balances.iter().collect();
-                sorted_balances.sort_by(|a, b| b.1.cmp(a.1));
-
-                // Optional: print top 10 balances
-                println!("Top 10 highest total balances:");
-                for (address, total_balance) in sorted_balances.iter().take(10) {
-                    println!(
-                        "{}: {} ({:.4}%)",
-                        address,
-                        bal(**total_balance),
-                        (bal(**total_balance) / bal(total_issuance)) * 100.0
-                    );
-                }
-                Ok(())
-            } else {
-                Ok(())
+            let siblings = claim.proof
+                .iter()
+                .map(|hash| merkle::hash_from_hex(hash))
+                .collect::<Result<Vec<_>>>()?;
+            if merkle::compute_root(&leaf, &siblings) != root {
+                bail!("Proof of {} does not lead to the given root", claim.address);
             }
+            println!("Valid claim of {} for {}", claim.address, claim.amount);
+
+            Ok(())
         }
     }
 }
-
-fn bal(balance: u128) -> f64 {
-    (balance as f64) / 1_000_000_000f64
-}
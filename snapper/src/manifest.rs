@@ -0,0 +1,183 @@
+use serde::{ Serialize, Deserialize };
+use anyhow::{ Result, anyhow, bail };
+use sp_core::hashing::sha2_256;
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::time::{ SystemTime, UNIX_EPOCH };
+
+use crate::SnapshotBlock;
+use crate::checkpoint::{ CHECKPOINT_FILE, remove_partial };
+
+pub const MANIFEST_FILE: &str = "manifest.json";
+
+/// Files `snap` writes to a snapshot directory, all covered by the manifest.
+pub const SNAPSHOT_FILES: [&str; 7] = [
+    "block.json",
+    "metadata.scale",
+    "totals.json",
+    "accounts.json",
+    "stake.json",
+    "errors.json",
+    "total_balances.json",
+];
+
+/// Files only some snapshots have, covered by the manifest when present.
+pub const OPTIONAL_FILES: [&str; 5] = [
+    "holdings.json",
+    "header.json",
+    "proofs.jsonl",
+    "policy.json",
+    "policy_audit.json",
+];
+
+/// Provenance of a snapshot directory. Written last by `snap`, and checked by
+/// every command that reads a snapshot.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Manifest {
+    /// Node the snapshot was taken from
+    pub url: String,
+    /// Every node the crawl was spread over, `url` first
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub endpoints: Vec<String>,
+    pub block: SnapshotBlock,
+    pub spec_version: u32,
+    /// Hex encoded SHA-256 of metadata.scale, the SCALE encoded metadata at
+    /// the block
+    pub metadata_hash: String,
+    /// Unix time in seconds at which the snapshot finished
+    pub taken_at: u64,
+    pub counts: ManifestCounts,
+    /// Hex encoded SHA-256 of every file in the directory, by file name
+    pub files: BTreeMap<String, String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ManifestCounts {
+    pub accounts: u64,
+    pub stake: u64,
+    /// Entries that failed to decode, recorded in errors.json
+    pub failed: u64,
+    /// Addresses in total_balances.json
+    pub balances: u64,
+}
+
+impl Manifest {
+    /// Builds the manifest of a finished snapshot directory, hashing its files.
+    pub async fn new(
+        dir: &Path,
+        url: &str,
+        endpoints: &[String],
+        block: SnapshotBlock,
+        spec_version: u32,
+        counts: ManifestCounts
+    ) -> Result<Self> {
+        let mut files = BTreeMap::new();
+        for file in SNAPSHOT_FILES {
+            files.insert(file.to_string(), file_hash(&dir.join(file)).await?);
+        }
+        for file in OPTIONAL_FILES {
+            if dir.join(file).exists() {
+                files.insert(file.to_string(), file_hash(&dir.join(file)).await?);
+            }
+        }
+
+        Ok(Self {
+            url: url.to_string(),
+            endpoints: endpoints.to_vec(),
+            block,
+            spec_version,
+            metadata_hash: files["metadata.scale"].clone(),
+            taken_at: SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs(),
+            counts,
+            files,
+        })
+    }
+
+    pub async fn save(&self, dir: &Path) -> Result<()> {
+        let json = serde_json::to_string_pretty(self)?;
+        tokio::fs::write(dir.join(MANIFEST_FILE), json).await?;
+
+        Ok(())
+    }
+
+    /// Loads the manifest of a snapshot directory and checks every snapshot
+    /// file against its recorded checksum.
+    pub async fn verify(dir: &Path) -> Result<Self> {
+        let path = dir.join(MANIFEST_FILE);
+        let json = tokio::fs
+            ::read_to_string(&path).await
+            .map_err(|err| anyhow!("Cannot read {}: {}", path.display(), err))?;
+        let manifest: Self = serde_json::from_str(&json)?;
+
+        for file in SNAPSHOT_FILES {
+            let expected = manifest.files
+                .get(file)
+                .ok_or_else(|| anyhow!("{} does not cover {}", path.display(), file))?;
+            if &file_hash(&dir.join(file)).await? != expected {
+                bail!("{} does not match its checksum in {}", file, path.display());
+            }
+        }
+        for file in OPTIONAL_FILES {
+            let expected = manifest.files.get(file);
+            if dir.join(file).exists() && expected.is_none() {
+                bail!("{} does not cover {}", path.display(), file);
+            }
+            if let Some(expected) = expected && &file_hash(&dir.join(file)).await? != expected {
+                bail!("{} does not match its checksum in {}", file, path.display());
+            }
+        }
+
+        Ok(manifest)
+    }
+}
+
+/// Removes the optional files an earlier snapshot left in `dir`, so that the
+/// manifest of a new one only covers the files it wrote itself. Only files
+/// the manifest in `dir` lists are removed, or any when a checkpoint shows an
+/// interrupted run wrote them, and never one of the `keep` paths, e.g. the
+/// policy file the new snapshot applies. Fails on any other optional file, as
+/// the new manifest would cover it.
+pub async fn remove_optional_files(dir: &Path, keep: &[&Path]) -> Result<()> {
+    let listed = match tokio::fs::read_to_string(dir.join(MANIFEST_FILE)).await {
+        Ok(json) => serde_json::from_str::<Manifest>(&json)?.files,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => BTreeMap::new(),
+        Err(err) => return Err(err.into()),
+    };
+    let interrupted = dir.join(CHECKPOINT_FILE).exists();
+    for file in OPTIONAL_FILES {
+        let path = dir.join(file);
+        if !path.exists() || is_kept(&path, keep).await {
+            continue;
+        }
+        if !interrupted && !listed.contains_key(file) {
+            bail!("{} was not written by a snapshot, move it out of {}", file, dir.display());
+        }
+        remove_partial(&path).await?;
+    }
+
+    Ok(())
+}
+
+/// Whether `path` is the same file as one of `keep`.
+pub async fn is_kept(path: &Path, keep: &[&Path]) -> bool {
+    let Ok(path) = tokio::fs::canonicalize(path).await else {
+        return false;
+    };
+    for kept in keep {
+        if tokio::fs::canonicalize(kept).await.is_ok_and(|kept| kept == path) {
+            return true;
+        }
+    }
+
+    false
+}
+
+/// SHA-256 of manifest.json exactly as it is stored, the message snapshot
+/// signatures are made over.
+pub async fn manifest_digest(dir: &Path) -> Result<[u8; 32]> {
+    Ok(sha2_256(&tokio::fs::read(dir.join(MANIFEST_FILE)).await?))
+}
+
+async fn file_hash(path: &Path) -> Result<String> {
+    Ok(hex::encode(sha2_256(&tokio::fs::read(path).await?)))
+}
@@ -0,0 +1,160 @@
+//! Merkle tree over airdrop claims.
+//!
+//! Each leaf is the SCALE encoding of `(AccountId32, u128)`, i.e. the 32 byte
+//! account id followed by the amount as 16 little-endian bytes, and is hashed
+//! with blake2_256. A parent node is the blake2_256 of its two children
+//! concatenated in ascending byte order, so proofs carry no left/right flags.
+//! A node without a sibling is carried up to the next level unchanged.
+use serde::{ Serialize, Deserialize };
+use parity_scale_codec::{ Decode, Encode };
+use sp_core::hashing::blake2_256;
+use anyhow::{ Result, anyhow, bail };
+
+pub type Hash = [u8; 32];
+
+/// Everything needed to claim and verify a single airdrop allocation.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ClaimProof {
+    pub address: String,
+    pub amount: u128,
+    /// Hex encoded SCALE leaf, `(AccountId32, u128)`
+    pub leaf: String,
+    /// Hex encoded sibling hashes from the leaf up to the root
+    pub proof: Vec<String>,
+}
+
+/// Published description of a Merkle tree.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct MerkleRoot {
+    pub root: String,
+    pub leaves: usize,
+    pub total: u128,
+}
+
+pub fn encode_leaf(account: [u8; 32], amount: u128) -> Vec<u8> {
+    (account, amount).encode()
+}
+
+pub fn decode_leaf(leaf: &[u8]) -> Result<([u8; 32], u128)> {
+    Ok(<([u8; 32], u128)>::decode(&mut &leaf[..])?)
+}
+
+fn hash_pair(a: &Hash, b: &Hash) -> Hash {
+    let (low, high) = if a <= b { (a, b) } else { (b, a) };
+    blake2_256(&[low.as_slice(), high.as_slice()].concat())
+}
+
+pub struct MerkleTree {
+    /// Levels from the leaf hashes up to the single root hash.
+    levels: Vec<Vec<Hash>>,
+}
+
+impl MerkleTree {
+    /// Builds the tree over already encoded leaves, keeping their order.
+    pub fn new(leaves: &[Vec<u8>]) -> Result<Self> {
+        if leaves.is_empty() {
+            bail!("Cannot build a Merkle tree without leaves");
+        }
+
+        let mut levels = vec![leaves.iter().map(|leaf| blake2_256(leaf)).collect::<Vec<Hash>>()];
+        while levels.last().map(|level| level.len() > 1).unwrap_or(false) {
+            let next = levels
+                .last()
+                .unwrap()
+                .chunks(2)
+                .map(|pair| if pair.len() == 2 { hash_pair(&pair[0], &pair[1]) } else { pair[0] })
+                .collect();
+            levels.push(next);
+        }
+
+        Ok(Self { levels })
+    }
+
+    pub fn root(&self) -> Hash {
+        self.levels.last().unwrap()[0]
+    }
+
+    /// Sibling hashes needed to recompute the root from the leaf at `index`.
+    pub fn proof(&self, mut index: usize) -> Vec<Hash> {
+        let mut proof = Vec::new();
+        for level in &self.levels[..self.levels.len() - 1] {
+            if let Some(sibling) = level.get(index ^ 1) {
+                proof.push(*sibling);
+            }
+            index /= 2;
+        }
+
+        proof
+    }
+}
+
+/// Recomputes the root from an encoded leaf and its proof.
+pub fn compute_root(leaf: &[u8], proof: &[Hash]) -> Hash {
+    proof.iter().fold(blake2_256(leaf), |node, sibling| hash_pair(&node, sibling))
+}
+
+pub fn to_hex(bytes: &[u8]) -> String {
+    format!("0x{}", hex::encode(bytes))
+}
+
+pub fn from_hex(value: &str) -> Result<Vec<u8>> {
+    Ok(hex::decode(value.trim_start_matches("0x"))?)
+}
+
+pub fn hash_from_hex(value: &str) -> Result<Hash> {
+    from_hex(value)?.try_into().map_err(|_| anyhow!("{} is not a 32 byte hash", value))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn leaves(count: u8) -> Vec<Vec<u8>> {
+        (0..count).map(|idx| encode_leaf([idx; 32], u128::from(idx) * 1000)).collect()
+    }
+
+    #[test]
+    fn every_leaf_proves_the_root() {
+        for count in 1..=9 {
+            let leaves = leaves(count);
+            let tree = MerkleTree::new(&leaves).unwrap();
+            for (idx, leaf) in leaves.iter().enumerate() {
+                assert_eq!(compute_root(leaf, &tree.proof(idx)), tree.root(), "{} leaves", count);
+            }
+        }
+    }
+
+    #[test]
+    fn unpaired_node_is_carried_up() {
+        let leaves = leaves(3);
+        let hashes = leaves.iter().map(|leaf| blake2_256(leaf)).collect::<Vec<Hash>>();
+        let tree = MerkleTree::new(&leaves).unwrap();
+
+        assert_eq!(tree.root(), hash_pair(&hash_pair(&hashes[0], &hashes[1]), &hashes[2]));
+        assert_eq!(tree.proof(2), vec![hash_pair(&hashes[0], &hashes[1])]);
+    }
+
+    #[test]
+    fn single_leaf_is_the_root() {
+        let leaves = leaves(1);
+        let tree = MerkleTree::new(&leaves).unwrap();
+
+        assert_eq!(tree.root(), blake2_256(&leaves[0]));
+        assert!(tree.proof(0).is_empty());
+    }
+
+    #[test]
+    fn changed_amount_does_not_prove_the_root() {
+        let leaves = leaves(5);
+        let tree = MerkleTree::new(&leaves).unwrap();
+        let forged = encode_leaf([3; 32], 4000);
+
+        assert_ne!(compute_root(&forged, &tree.proof(3)), tree.root());
+        assert_eq!(decode_leaf(&leaves[3]).unwrap(), ([3; 32], 3000));
+    }
+
+    #[test]
+    fn no_leaves_is_an_error() {
+        assert!(MerkleTree::new(&[]).is_err());
+    }
+}
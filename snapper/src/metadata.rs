@@ -0,0 +1,220 @@
+//! Decoding of storage keys and values against the metadata of the snapshot
+//! block, so that snapshots keep working across runtime upgrades.
+
+use anyhow::{ Result, anyhow, bail };
+use frame_decode::storage::{ StorageTypeInfo, decode_storage_key };
+use frame_metadata::{ RuntimeMetadata, RuntimeMetadataPrefixed };
+use parity_scale_codec::Decode;
+use scale_value::{ At, Composite, Value, ValueDef };
+use subxt::ext::scale_decode::TypeResolver;
+
+use crate::client::storage_prefix;
+
+/// Runtime metadata of a block, as returned by `state_getMetadata`.
+pub struct ChainMetadata(RuntimeMetadata);
+
+impl ChainMetadata {
+    pub fn decode(bytes: &[u8]) -> Result<Self> {
+        let metadata = RuntimeMetadataPrefixed::decode(&mut &bytes[..])?.1;
+        match metadata {
+            RuntimeMetadata::V14(_) | RuntimeMetadata::V15(_) | RuntimeMetadata::V16(_) => {
+                Ok(Self(metadata))
+            }
+            _ => bail!("Unsupported metadata version {}", metadata.version()),
+        }
+    }
+
+    pub fn has_entry(&self, pallet: &str, entry: &str) -> bool {
+        match &self.0 {
+            RuntimeMetadata::V14(v14) => v14.get_storage_info(pallet, entry).is_ok(),
+            RuntimeMetadata::V15(v15) => v15.get_storage_info(pallet, entry).is_ok(),
+            RuntimeMetadata::V16(v16) => v16.get_storage_info(pallet, entry).is_ok(),
+            _ => false,
+        }
+    }
+
+    /// Spec version of the runtime, read from the `System.Version` constant for
+    /// when no node is around to ask.
+    pub fn spec_version(&self) -> Result<u32> {
+        let (constant, types) = match &self.0 {
+            RuntimeMetadata::V14(v14) => (
+                v14.pallets.iter()
+                    .find(|pallet| pallet.name == "System")
+                    .and_then(|pallet| pallet.constants.iter().find(|c| c.name == "Version"))
+                    .map(|constant| (constant.ty.id, &constant.value)),
+                &v14.types,
+            ),
+            RuntimeMetadata::V15(v15) => (
+                v15.pallets.iter()
+                    .find(|pallet| pallet.name == "System")
+                    .and_then(|pallet| pallet.constants.iter().find(|c| c.name == "Version"))
+                    .map(|constant| (constant.ty.id, &constant.value)),
+                &v15.types,
+            ),
+            RuntimeMetadata::V16(v16) => (
+                v16.pallets.iter()
+                    .find(|pallet| pallet.name == "System")
+                    .and_then(|pallet| pallet.constants.iter().find(|c| c.name == "Version"))
+                    .map(|constant| (constant.ty.id, &constant.value)),
+                &v16.types,
+            ),
+            _ => unreachable!("unsupported versions are rejected on decode"),
+        };
+        let (type_id, bytes) = constant.ok_or_else(|| anyhow!("Metadata has no System.Version"))?;
+        let version = scale_value::scale::decode_as_type(&mut &bytes[..], type_id, types)?;
+
+        let spec_version = field_u128(&version, "spec_version")
+            .ok_or_else(|| anyhow!("System.Version has no spec_version"))?;
+        Ok(u32::try_from(spec_version)?)
+    }
+
+    /// Splits a storage key into the SCALE encoded values of its key parts.
+    /// Fails for parts stored behind an opaque hasher.
+    pub fn decode_key(&self, pallet: &str, entry: &str, key: &[u8]) -> Result<Vec<Vec<u8>>> {
+        match &self.0 {
+            RuntimeMetadata::V14(v14) => key_parts(v14, &v14.types, pallet, entry, key),
+            RuntimeMetadata::V15(v15) => key_parts(v15, &v15.types, pallet, entry, key),
+            RuntimeMetadata::V16(v16) => key_parts(v16, &v16.types, pallet, entry, key),
+            _ => unreachable!("unsupported versions are rejected on decode"),
+        }
+    }
+
+    pub fn decode_value(&self, pallet: &str, entry: &str, bytes: &[u8]) -> Result<Value<u32>> {
+        match &self.0 {
+            RuntimeMetadata::V14(v14) => value(v14, &v14.types, pallet, entry, bytes),
+            RuntimeMetadata::V15(v15) => value(v15, &v15.types, pallet, entry, bytes),
+            RuntimeMetadata::V16(v16) => value(v16, &v16.types, pallet, entry, bytes),
+            _ => unreachable!("unsupported versions are rejected on decode"),
+        }
+    }
+
+    /// Value a storage entry reads as when nothing is stored under its key.
+    pub fn default_value(&self, pallet: &str, entry: &str) -> Result<Value<u32>> {
+        match &self.0 {
+            RuntimeMetadata::V14(v14) => default_value(v14, &v14.types, pallet, entry),
+            RuntimeMetadata::V15(v15) => default_value(v15, &v15.types, pallet, entry),
+            RuntimeMetadata::V16(v16) => default_value(v16, &v16.types, pallet, entry),
+            _ => unreachable!("unsupported versions are rejected on decode"),
+        }
+    }
+}
+
+fn key_parts<Info, Resolver>(
+    info: &Info,
+    types: &Resolver,
+    pallet: &str,
+    entry: &str,
+    key: &[u8]
+) -> Result<Vec<Vec<u8>>>
+    where Info: StorageTypeInfo<TypeId = u32>, Resolver: TypeResolver<TypeId = u32>
+{
+    // frame-decode slices the prefix off without checking the key's length
+    if !key.starts_with(&storage_prefix(pallet, entry)) {
+        bail!("0x{} is not a {}.{} key", hex::encode(key), pallet, entry);
+    }
+    let cursor = &mut &key[..];
+    let decoded = decode_storage_key(pallet, entry, cursor, info, types).map_err(|err|
+        anyhow!("Cannot decode {}.{} key 0x{}: {}", pallet, entry, hex::encode(key), err)
+    )?;
+    if !cursor.is_empty() {
+        bail!("{}.{} key 0x{} has trailing bytes", pallet, entry, hex::encode(key));
+    }
+
+    decoded
+        .parts()
+        .map(|part| {
+            part.value()
+                .map(|value| key[value.range()].to_vec())
+                .ok_or_else(|| anyhow!("{}.{} key parts are opaque", pallet, entry))
+        })
+        .collect()
+}
+
+fn value<Info, Resolver>(
+    info: &Info,
+    types: &Resolver,
+    pallet: &str,
+    entry: &str,
+    bytes: &[u8]
+) -> Result<Value<u32>>
+    where Info: StorageTypeInfo<TypeId = u32>, Resolver: TypeResolver<TypeId = u32>
+{
+    let value_id = info
+        .get_storage_info(pallet, entry)
+        .map_err(|err| anyhow!("{}", err))?
+        .value_id;
+    let cursor = &mut &bytes[..];
+    let value = scale_value::scale::decode_as_type(cursor, value_id, types)?;
+    if !cursor.is_empty() {
+        bail!("{}.{} value has {} trailing bytes", pallet, entry, cursor.len());
+    }
+
+    Ok(value)
+}
+
+fn default_value<Info, Resolver>(
+    info: &Info,
+    types: &Resolver,
+    pallet: &str,
+    entry: &str
+) -> Result<Value<u32>>
+    where Info: StorageTypeInfo<TypeId = u32>, Resolver: TypeResolver<TypeId = u32>
+{
+    let storage = info.get_storage_info(pallet, entry).map_err(|err| anyhow!("{}", err))?;
+    let bytes = storage.default_value
+        .ok_or_else(|| anyhow!("{}.{} has no default value", pallet, entry))?;
+
+    Ok(scale_value::scale::decode_as_type(&mut &bytes[..], storage.value_id, types)?)
+}
+
+/// Reads an unsigned integer field out of a decoded value.
+pub fn field_u128(value: &Value<u32>, name: &str) -> Option<u128> {
+    value.at(name).and_then(|field| field.as_u128())
+}
+
+/// Reads an account id out of a decoded `AccountId32`, 32 bytes wrapped in a
+/// newtype.
+pub fn account_id(value: &Value<u32>) -> Option<[u8; 32]> {
+    match &value.value {
+        ValueDef::Composite(Composite::Unnamed(fields)) if fields.len() == 1 => {
+            account_id(&fields[0])
+        }
+        ValueDef::Composite(bytes) => {
+            let bytes = bytes
+                .values()
+                .map(|byte| byte.as_u128().and_then(|byte| u8::try_from(byte).ok()))
+                .collect::<Option<Vec<u8>>>()?;
+            bytes.try_into().ok()
+        }
+        _ => None,
+    }
+}
+
+/// Name of the enum variant held by a field of a decoded value.
+pub fn variant_name<'a>(value: &'a Value<u32>, name: &str) -> Option<&'a str> {
+    match &value.at(name)?.value {
+        ValueDef::Variant(variant) => Some(variant.name.as_str()),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn metadata() -> ChainMetadata {
+        let path = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("metadata.commune.scale");
+        ChainMetadata::decode(&std::fs::read(path).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn short_and_foreign_keys_fail_to_decode() {
+        let metadata = metadata();
+        let account = [storage_prefix("System", "Account"), vec![7; 48]].concat();
+
+        assert!(metadata.decode_key("System", "Account", &account).is_ok());
+        assert!(metadata.decode_key("System", "Account", &[0x26; 5]).is_err());
+        assert!(metadata.decode_key("System", "Account", &account[..20]).is_err());
+        assert!(metadata.decode_key("SubspaceModule", "StakeTo", &account).is_err());
+    }
+}
@@ -0,0 +1,366 @@
+use serde::{ Serialize, Deserialize };
+use anyhow::{ Result, anyhow };
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use crate::balances::Balance;
+use crate::profile::NetworkProfile;
+
+/// Copy of the applied policy kept in the snapshot directory.
+pub const POLICY_FILE: &str = "policy.json";
+pub const AUDIT_FILE: &str = "policy_audit.json";
+
+/// Stands for the `GovernanceModule.DaoTreasuryAddress` recorded in the
+/// snapshot wherever a policy expects an address.
+pub const DAO_TREASURY: &str = "dao-treasury";
+
+/// Rules applied to the aggregated balances of a snapshot, in order: first
+/// exclusions, then remaps, then adjustments.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(deny_unknown_fields)]
+pub struct Policy {
+    /// Addresses dropped from the snapshot, e.g. burn addresses
+    #[serde(default)]
+    pub exclude: Vec<Exclude>,
+    /// Addresses whose holdings are moved to another address, e.g. exchange
+    /// wallets
+    #[serde(default)]
+    pub remap: Vec<Remap>,
+    /// Manual corrections of the free balance of an address
+    #[serde(default)]
+    pub adjust: Vec<Adjust>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct Exclude {
+    pub address: String,
+    pub reason: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct Remap {
+    pub from: String,
+    pub to: String,
+    pub reason: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct Adjust {
+    pub address: String,
+    /// Signed amount added to the free balance, in the chain's base unit
+    pub delta: i128,
+    pub reason: String,
+}
+
+/// A rule that changed the balance of an address.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct AuditEntry {
+    pub rule: String,
+    pub address: String,
+    pub reason: String,
+    pub before: Balance,
+    pub after: Balance,
+}
+
+impl Policy {
+    pub async fn load(path: &Path) -> Result<Self> {
+        let json = tokio::fs::read_to_string(path).await?;
+        serde_json
+            ::from_str(&json)
+            .map_err(|err| anyhow!("Invalid policy {}: {}", path.display(), err))
+    }
+
+    /// Applies the rules to `balances` and returns an audit entry for every
+    /// address a rule changed. Rules naming an address without a balance do
+    /// not fire.
+    pub fn apply(
+        &self,
+        balances: &mut BTreeMap<String, Balance>,
+        dao_treasury: Option<&str>,
+        profile: &NetworkProfile
+    ) -> Result<Vec<AuditEntry>> {
+        let resolve = |address: &str| -> Result<String> {
+            let address = match address {
+                DAO_TREASURY => dao_treasury.ok_or_else(||
+                    anyhow!("Policy refers to the DAO treasury but the snapshot has none")
+                )?,
+                address => address,
+            };
+            // Normalise to the prefix the balances are keyed by
+            Ok(profile.encode_address(NetworkProfile::decode_address(address)?))
+        };
+        let mut audit = Vec::new();
+        let mut record = |rule: &str, address: &str, reason: &str, before, after| {
+            audit.push(AuditEntry {
+                rule: rule.to_string(),
+                address: address.to_string(),
+                reason: reason.to_string(),
+                before,
+                after,
+            });
+        };
+
+        for rule in &self.exclude {
+            let address = resolve(&rule.address)?;
+            let Some(before) = balances.remove(&address) else {
+                println!("Policy: nothing to exclude for {}", address);
+                continue;
+            };
+            record("exclude", &address, &rule.reason, before, Balance::default());
+        }
+
+        for rule in &self.remap {
+            let (from, to) = (resolve(&rule.from)?, resolve(&rule.to)?);
+            let Some(moved) = balances.remove(&from) else {
+                println!("Policy: nothing to remap from {}", from);
+                continue;
+            };
+            let before = balances.get(&to).cloned().unwrap_or_default();
+            let mut after = before.clone();
+            after.free += moved.free;
+            after.reserved += moved.reserved;
+            after.frozen += moved.frozen;
+            after.staked_out += moved.staked_out;
+            after.staked_in += moved.staked_in;
+            for (source, amount) in &moved.held {
+                *after.held.entry(*source).or_default() += amount;
+            }
+            after.update_total();
+            balances.insert(to.clone(), after.clone());
+
+            record("remap", &from, &rule.reason, moved, Balance::default());
+            record("remap", &to, &rule.reason, before, after);
+        }
+
+        for rule in &self.adjust {
+            let address = resolve(&rule.address)?;
+            let Some(balance) = balances.get_mut(&address) else {
+                println!("Policy: nothing to adjust for {}", address);
+                continue;
+            };
+            let before = balance.clone();
+            balance.free = balance.free
+                .checked_add_signed(rule.delta)
+                .ok_or_else(|| anyhow!("Adjusting {} by {} overflows", address, rule.delta))?;
+            balance.update_total();
+            record("adjust", &address, &rule.reason, before, balance.clone());
+        }
+
+        Ok(audit)
+    }
+}
+
+pub async fn save_audit(dir: &Path, audit: &[AuditEntry]) -> Result<()> {
+    let json = serde_json::to_string_pretty(audit)?;
+    tokio::fs::write(dir.join(AUDIT_FILE), json).await?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::holdings::HoldingSource;
+
+    fn profile(ss58_prefix: u16) -> NetworkProfile {
+        NetworkProfile {
+            url: String::new(),
+            ss58_prefix,
+            decimals: 9,
+            existential_deposit: 500,
+            sources: Vec::new(),
+        }
+    }
+
+    fn address(byte: u8) -> String {
+        profile(42).encode_address([byte; 32])
+    }
+
+    fn balance(free: u128, staked_out: u128) -> Balance {
+        let mut balance = Balance { free, staked_out, ..Default::default() };
+        balance.update_total();
+        balance
+    }
+
+    /// Balances of the accounts `[1; 32]` to `[3; 32]`, the first of which
+    /// also holds an open proposal deposit.
+    fn initial_balances() -> BTreeMap<String, Balance> {
+        let mut first = balance(1000, 200);
+        first.held.insert(HoldingSource::Proposals, 50);
+        first.update_total();
+
+        BTreeMap::from([
+            (address(1), first),
+            (address(2), balance(300, 0)),
+            (address(3), balance(40, 0)),
+        ])
+    }
+
+    fn policy(json: &str) -> Policy {
+        serde_json::from_str(json).unwrap()
+    }
+
+    fn exclude(address: &str) -> String {
+        format!(r#"{{ "exclude": [{{ "address": "{}", "reason": "burn" }}] }}"#, address)
+    }
+
+    fn apply(policy: &Policy, balances: &mut BTreeMap<String, Balance>) -> Vec<AuditEntry> {
+        policy.apply(balances, Some(&address(3)), &profile(42)).unwrap()
+    }
+
+    #[test]
+    fn exclude_drops_the_address() {
+        let mut balances = initial_balances();
+        let json = exclude(&address(2));
+
+        let audit = apply(&policy(&json), &mut balances);
+
+        assert!(!balances.contains_key(&address(2)));
+        assert_eq!(audit.len(), 1);
+        assert_eq!((audit[0].rule.as_str(), audit[0].reason.as_str()), ("exclude", "burn"));
+        assert_eq!(audit[0].before, balance(300, 0));
+        assert_eq!(audit[0].after, Balance::default());
+    }
+
+    #[test]
+    fn remap_into_existing_address_adds_every_part() {
+        let mut balances = initial_balances();
+        let json = format!(
+            r#"{{ "remap": [{{ "from": "{}", "to": "{}", "reason": "exchange" }}] }}"#,
+            address(1),
+            address(2)
+        );
+
+        let audit = apply(&policy(&json), &mut balances);
+
+        let mut expected = balance(1300, 200);
+        expected.held.insert(HoldingSource::Proposals, 50);
+        expected.update_total();
+        assert!(!balances.contains_key(&address(1)));
+        assert_eq!(balances[&address(2)], expected);
+        assert_eq!(balances[&address(2)].total, 1550);
+
+        assert_eq!(audit.len(), 2);
+        assert_eq!(audit[0].address, address(1));
+        assert_eq!(audit[0].before, initial_balances()[&address(1)]);
+        assert_eq!(audit[0].after, Balance::default());
+        assert_eq!(audit[1].address, address(2));
+        assert_eq!(audit[1].before, balance(300, 0));
+        assert_eq!(audit[1].after, expected);
+    }
+
+    #[test]
+    fn remap_to_new_address_moves_the_balance() {
+        let mut balances = initial_balances();
+        let json = format!(
+            r#"{{ "remap": [{{ "from": "{}", "to": "{}", "reason": "exchange" }}] }}"#,
+            address(2),
+            address(9)
+        );
+
+        let audit = apply(&policy(&json), &mut balances);
+
+        assert_eq!(balances[&address(9)], balance(300, 0));
+        assert_eq!(audit[1].before, Balance::default());
+    }
+
+    /// Exclusions run before remaps and adjustments after them, whatever the
+    /// order of the rules in the file.
+    #[test]
+    fn rules_apply_in_order() {
+        let mut balances = initial_balances();
+        let json = format!(
+            r#"{{
+                "adjust": [{{ "address": "{to}", "delta": -100, "reason": "fix" }}],
+                "remap": [{{ "from": "{from}", "to": "{to}", "reason": "exchange" }}],
+                "exclude": [{{ "address": "{to}", "reason": "burn" }}]
+            }}"#,
+            from = address(2),
+            to = address(1)
+        );
+
+        let audit = apply(&policy(&json), &mut balances);
+
+        let rules = audit.iter().map(|entry| entry.rule.as_str()).collect::<Vec<_>>();
+        assert_eq!(rules, ["exclude", "remap", "remap", "adjust"]);
+        // The remap starts from the excluded, now empty, address
+        assert_eq!(audit[2].before, Balance::default());
+        assert_eq!(audit[3].before, balance(300, 0));
+        assert_eq!(balances[&address(1)], balance(200, 0));
+    }
+
+    #[test]
+    fn dao_treasury_alias_resolves_to_the_treasury() {
+        let mut balances = initial_balances();
+        let json = r#"{ "exclude": [{ "address": "dao-treasury", "reason": "treasury" }] }"#;
+
+        let audit = apply(&policy(json), &mut balances);
+
+        assert_eq!(audit[0].address, address(3));
+        assert!(!balances.contains_key(&address(3)));
+        assert!(policy(json).apply(&mut initial_balances(), None, &profile(42)).is_err());
+    }
+
+    #[test]
+    fn addresses_are_normalised_to_the_network_prefix() {
+        let mut balances = initial_balances();
+        let polkadot = profile(0).encode_address([2; 32]);
+        let json = exclude(&polkadot);
+
+        let audit = apply(&policy(&json), &mut balances);
+
+        assert_eq!(audit[0].address, address(2));
+        assert!(!balances.contains_key(&address(2)));
+    }
+
+    #[test]
+    fn adjust_changes_free_and_total() {
+        let mut balances = initial_balances();
+        let json = format!(
+            r#"{{ "adjust": [{{ "address": "{}", "delta": -40, "reason": "fix" }}] }}"#,
+            address(3)
+        );
+
+        let audit = apply(&policy(&json), &mut balances);
+
+        assert_eq!(audit[0].before, balance(40, 0));
+        assert_eq!(audit[0].after, balance(0, 0));
+        assert_eq!(balances[&address(3)], balance(0, 0));
+    }
+
+    #[test]
+    fn adjust_that_overflows_fails() {
+        for (free, delta) in [(40, -41), (u128::MAX, 1)] {
+            let mut balances = BTreeMap::from([(address(3), balance(free, 0))]);
+            let json = format!(
+                r#"{{ "adjust": [{{ "address": "{}", "delta": {}, "reason": "fix" }}] }}"#,
+                address(3),
+                delta
+            );
+
+            let result = policy(&json).apply(&mut balances, None, &profile(42));
+            assert!(result.unwrap_err().to_string().contains("overflows"));
+        }
+    }
+
+    #[test]
+    fn rules_without_a_balance_do_not_fire() {
+        let mut balances = initial_balances();
+        let json = format!(
+            r#"{{
+                "exclude": [{{ "address": "{a}", "reason": "burn" }}],
+                "remap": [{{ "from": "{a}", "to": "{b}", "reason": "exchange" }}],
+                "adjust": [{{ "address": "{a}", "delta": 5, "reason": "fix" }}]
+            }}"#,
+            a = address(7),
+            b = address(1)
+        );
+
+        assert!(apply(&policy(&json), &mut balances).is_empty());
+        assert_eq!(balances, initial_balances());
+    }
+}
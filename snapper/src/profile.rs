@@ -0,0 +1,79 @@
+use serde::{ Serialize, Deserialize };
+use sp_core::crypto::{ AccountId32, Ss58AddressFormat, Ss58Codec };
+use anyhow::{ Result, anyhow };
+use std::collections::HashMap;
+use std::path::Path;
+
+use crate::holdings::HoldingSource;
+
+/// Profiles shipped with snapper, used when no `--profiles` file is given.
+const DEFAULT_PROFILES: &str = include_str!("../networks.json");
+
+/// Connection and token parameters of a named network.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct NetworkProfile {
+    pub url: String,
+    pub ss58_prefix: u16,
+    pub decimals: u32,
+    pub existential_deposit: u128,
+    /// Holding sources snapshots of the network read besides accounts and stake
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub sources: Vec<HoldingSource>,
+}
+
+impl NetworkProfile {
+    /// Loads the profile called `name` from `path`, or from the built-in
+    /// profiles when no path is given.
+    pub fn load(path: Option<&Path>, name: &str) -> Result<Self> {
+        let json = match path {
+            Some(path) => std::fs::read_to_string(path)?,
+            None => DEFAULT_PROFILES.to_string(),
+        };
+        let mut profiles: HashMap<String, NetworkProfile> = serde_json::from_str(&json)?;
+
+        profiles.remove(name).ok_or_else(|| {
+            let mut known = profiles.keys().cloned().collect::<Vec<String>>();
+            known.sort();
+            anyhow!("Unknown network profile '{}', expected one of: {}", name, known.join(", "))
+        })
+    }
+
+    /// SS58-encodes a raw account id with this network's address prefix.
+    pub fn encode_address(&self, account: [u8; 32]) -> String {
+        AccountId32::from(account).to_ss58check_with_version(
+            Ss58AddressFormat::custom(self.ss58_prefix)
+        )
+    }
+
+    /// Decodes an SS58 address of any prefix into its raw account id.
+    pub fn decode_address(address: &str) -> Result<[u8; 32]> {
+        let (account, _) = AccountId32::from_ss58check_with_version(address).map_err(|err|
+            anyhow!("Invalid address {}: {:?}", address, err)
+        )?;
+        Ok(account.into())
+    }
+
+    /// Converts an amount in `source` units into this network's units.
+    pub fn convert_amount(&self, amount: u128, source: &NetworkProfile) -> Result<u128> {
+        if self.decimals >= source.decimals {
+            amount
+                .checked_mul(10u128.pow(self.decimals - source.decimals))
+                .ok_or_else(|| anyhow!("Amount {} overflows after conversion", amount))
+        } else {
+            Ok(amount / 10u128.pow(source.decimals - self.decimals))
+        }
+    }
+
+    /// Formats a raw balance as whole tokens with exact fixed-point decimals,
+    /// trailing zeros dropped.
+    pub fn bal(&self, balance: u128) -> String {
+        let unit = 10u128.pow(self.decimals);
+        let fraction = format!("{:0width$}", balance % unit, width = self.decimals as usize);
+        let fraction = fraction.trim_end_matches('0');
+        if fraction.is_empty() {
+            format!("{}", balance / unit)
+        } else {
+            format!("{}.{}", balance / unit, fraction)
+        }
+    }
+}
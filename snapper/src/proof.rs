@@ -0,0 +1,348 @@
+//! Storage read proofs. With `snap --proofs` every page of storage entries
+//! is accompanied by the trie nodes the node used to read it, checked against
+//! the state root of the snapshot block. The proofs, together with the block
+//! header, let anyone re-check the values of a snapshot without trusting the
+//! node it was taken from.
+//!
+//! A read proof shows that the proven keys hold the proven values at the block.
+//! To show that a map has no keys besides the proven ones, the crawl also
+//! proves the path to each map's prefix, and `check-proofs` walks the trie
+//! below it in the nodes of all proofs together. Any subtree the proofs leave
+//! out makes that walk fail.
+
+use serde::{ Serialize, Deserialize };
+use anyhow::{ Result, anyhow, bail };
+use parity_scale_codec::{ Decode, Encode };
+use sp_core::{ Blake2Hasher, hashing::blake2_256 };
+use sp_trie::{
+    LayoutV1,
+    MemoryDB,
+    StorageProof,
+    TrieDBBuilder,
+    TrieDBKeyIterator,
+    read_trie_value,
+};
+use subxt::config::substrate::{ BlakeTwo256, SubstrateHeader };
+use std::path::Path;
+
+pub const HEADER_FILE: &str = "header.json";
+pub const PROOFS_FILE: &str = "proofs.jsonl";
+
+/// Header of the snapshot block, to check the state root the proofs lead to.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct BlockHeader {
+    pub number: u64,
+    pub hash: String,
+    /// 0x-prefixed hex encoded state root
+    pub state_root: String,
+    /// 0x-prefixed hex encoded SCALE header, whose blake2_256 hash is `hash`
+    pub encoded: String,
+}
+
+impl BlockHeader {
+    pub fn new(header: &impl Encode) -> Result<Self> {
+        Self::from_encoded(header.encode())
+    }
+
+    fn from_encoded(encoded: Vec<u8>) -> Result<Self> {
+        let decoded = SubstrateHeader::<u64, BlakeTwo256>::decode(&mut &encoded[..])?;
+
+        Ok(Self {
+            number: decoded.number,
+            hash: format!("0x{}", hex::encode(blake2_256(&encoded))),
+            state_root: format!("{:?}", decoded.state_root),
+            encoded: format!("0x{}", hex::encode(&encoded)),
+        })
+    }
+
+    /// Recomputes the hash and the state root from the encoded header. Returns
+    /// the state root once both match the recorded ones.
+    pub fn verify(&self) -> Result<[u8; 32]> {
+        let computed = Self::from_encoded(hex::decode(self.encoded.trim_start_matches("0x"))?)?;
+        if computed.hash != self.hash || computed.state_root != self.state_root {
+            bail!("Encoded header of block #{} does not match its hash or state root", self.number);
+        }
+
+        root_from_hex(&self.state_root)
+    }
+
+    pub async fn save(&self, dir: &Path) -> Result<()> {
+        let json = serde_json::to_string_pretty(self)?;
+        tokio::fs::write(dir.join(HEADER_FILE), json).await?;
+
+        Ok(())
+    }
+
+    pub async fn load(dir: &Path) -> Result<Self> {
+        let json = tokio::fs::read_to_string(dir.join(HEADER_FILE)).await?;
+        Ok(serde_json::from_str(&json)?)
+    }
+}
+
+/// Read proof of one page of a storage map, a line of proofs.jsonl.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PageProof {
+    /// `Pallet.Entry` the keys belong to
+    pub map: String,
+    /// Hex encoded storage keys
+    pub keys: Vec<String>,
+    /// Hex encoded trie nodes
+    pub nodes: Vec<String>,
+}
+
+impl PageProof {
+    /// Checks the read proof of a freshly fetched page against `state_root`
+    /// and the values the node returned alongside it.
+    pub fn new(
+        map: &str,
+        state_root: &[u8; 32],
+        page: &[(Vec<u8>, Vec<u8>)],
+        nodes: Vec<Vec<u8>>
+    ) -> Result<Self> {
+        let keys = page.iter().map(|(key, _)| key.clone()).collect::<Vec<_>>();
+        let proof = Self {
+            map: map.to_string(),
+            keys: keys.iter().map(hex::encode).collect(),
+            nodes: nodes.iter().map(hex::encode).collect(),
+        };
+
+        let proven = check_proof(state_root, &keys, nodes)?;
+        for ((key, value), proven) in page.iter().zip(proven) {
+            if proven.as_ref() != Some(value) {
+                bail!(
+                    "Read proof of {} does not match the value returned for key 0x{}",
+                    map,
+                    hex::encode(key)
+                );
+            }
+        }
+
+        Ok(proof)
+    }
+
+    /// Proves the path to the keys under `prefix`, which places the keys of
+    /// the map in the trie even when it has none. The proof has no keys.
+    pub fn prefix(
+        map: &str,
+        state_root: &[u8; 32],
+        prefix: &[u8],
+        nodes: Vec<Vec<u8>>
+    ) -> Result<Self> {
+        let proof = Self {
+            map: map.to_string(),
+            keys: Vec::new(),
+            nodes: nodes.iter().map(hex::encode).collect(),
+        };
+        check_proof(state_root, &[prefix.to_vec()], nodes)?;
+
+        Ok(proof)
+    }
+
+    /// Reads the proven entries out of the proof nodes. Fails if a key lies
+    /// outside the `prefix` of the page's map, or if the nodes do not lead to
+    /// `state_root` or show one of the keys to be absent.
+    pub fn check(&self, state_root: &[u8; 32], prefix: &[u8]) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        let keys = self.keys.iter().map(hex::decode).collect::<Result<Vec<_>, _>>()?;
+        if let Some(key) = keys.iter().find(|key| !key.starts_with(prefix)) {
+            bail!("Read proof of {} holds key 0x{} of another map", self.map, hex::encode(key));
+        }
+        let nodes = self.nodes.iter().map(hex::decode).collect::<Result<Vec<_>, _>>()?;
+        let values = check_proof(state_root, &keys, nodes)?;
+
+        keys.into_iter()
+            .zip(values)
+            .map(|(key, value)| match value {
+                Some(value) => Ok((key, value)),
+                None => Err(anyhow!("Read proof shows key 0x{} to be absent", hex::encode(key))),
+            })
+            .collect()
+    }
+}
+
+/// Reads `keys` from a read proof against `state_root`. A key the proof shows
+/// to be absent reads as `None`; a key the proof does not cover is an error.
+pub fn check_proof(
+    state_root: &[u8; 32],
+    keys: &[Vec<u8>],
+    nodes: Vec<Vec<u8>>
+) -> Result<Vec<Option<Vec<u8>>>> {
+    let db = StorageProof::new(nodes).into_memory_db::<Blake2Hasher>();
+    let root = sp_core::H256::from(*state_root);
+
+    keys.iter()
+        .map(|key| {
+            read_trie_value::<LayoutV1<Blake2Hasher>, _>(&db, &root, key, None, None).map_err(|err|
+                anyhow!("Read proof does not cover key 0x{}: {:?}", hex::encode(key), err)
+            )
+        })
+        .collect()
+}
+
+/// The trie nodes of all page proofs of a snapshot, merged to walk the maps
+/// they were read from.
+pub struct ProvenTrie {
+    db: MemoryDB<Blake2Hasher>,
+    root: sp_core::H256,
+}
+
+impl ProvenTrie {
+    pub fn new(state_root: &[u8; 32], pages: &[PageProof]) -> Result<Self> {
+        let nodes = pages
+            .iter()
+            .flat_map(|page| &page.nodes)
+            .map(hex::decode)
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(Self {
+            db: StorageProof::new(nodes).into_memory_db(),
+            root: sp_core::H256::from(*state_root),
+        })
+    }
+
+    /// Every key under `prefix`, in key order. Fails if the proofs leave out
+    /// part of the trie below the prefix, so the keys are all the map holds.
+    pub fn keys(&self, prefix: &[u8]) -> Result<Vec<Vec<u8>>> {
+        let trie = TrieDBBuilder::<LayoutV1<Blake2Hasher>>::new(&self.db, &self.root).build();
+        let incomplete = |err| {
+            anyhow!("Read proofs do not cover all keys under 0x{}: {:?}", hex::encode(prefix), err)
+        };
+
+        TrieDBKeyIterator::new_prefixed(&trie, prefix)
+            .map_err(incomplete)?
+            .map(|key| key.map_err(incomplete))
+            .collect()
+    }
+}
+
+pub fn root_from_hex(value: &str) -> Result<[u8; 32]> {
+    hex::decode(value.trim_start_matches("0x"))?
+        .try_into()
+        .map_err(|_| anyhow!("State root {} is not 32 bytes long", value))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use sp_trie::{ TrieMut, TrieDBMutBuilder, recorder::Recorder };
+
+    use crate::client::storage_prefix;
+
+    const MAP: &str = "System.Account";
+
+    fn map_key(byte: u8) -> Vec<u8> {
+        [storage_prefix("System", "Account"), vec![byte; 4]].concat()
+    }
+
+    /// A trie with three keys under the prefix of `MAP`, which branch right
+    /// below it, and the total issuance outside of it. The values are long
+    /// enough for the leaves to be stored apart from the branch.
+    fn trie() -> (MemoryDB<Blake2Hasher>, [u8; 32]) {
+        let mut db = MemoryDB::default();
+        let mut root = sp_core::H256::default();
+        {
+            let mut trie = TrieDBMutBuilder::<LayoutV1<Blake2Hasher>>::new(&mut db, &mut root)
+                .build();
+            for byte in [0x00, 0x40, 0x80] {
+                trie.insert(&map_key(byte), &[byte; 32]).unwrap();
+            }
+            trie.insert(&storage_prefix("Balances", "TotalIssuance"), &[1; 16]).unwrap();
+        }
+
+        (db, root.0)
+    }
+
+    /// Trie nodes a node would send to prove `keys`.
+    fn prove(db: &MemoryDB<Blake2Hasher>, root: &[u8; 32], keys: &[Vec<u8>]) -> Vec<Vec<u8>> {
+        let root = sp_core::H256::from(*root);
+        let recorder = Recorder::<Blake2Hasher>::default();
+        for key in keys {
+            let mut trie_recorder = recorder.as_trie_recorder(root);
+            read_trie_value::<LayoutV1<Blake2Hasher>, _>(
+                db,
+                &root,
+                key,
+                Some(&mut trie_recorder),
+                None
+            ).unwrap();
+        }
+
+        recorder.drain_storage_proof().into_iter_nodes().collect()
+    }
+
+    /// Proof of the path to the map's prefix and of a page of `keys`.
+    fn pages(db: &MemoryDB<Blake2Hasher>, root: &[u8; 32], keys: &[Vec<u8>]) -> Vec<PageProof> {
+        let prefix = storage_prefix("System", "Account");
+        let entries = keys
+            .iter()
+            .map(|key| (key.clone(), vec![*key.last().unwrap(); 32]))
+            .collect::<Vec<_>>();
+
+        let nodes = prove(db, root, std::slice::from_ref(&prefix));
+
+        vec![
+            PageProof::prefix(MAP, root, &prefix, nodes).unwrap(),
+            PageProof::new(MAP, root, &entries, prove(db, root, keys)).unwrap()
+        ]
+    }
+
+    #[test]
+    fn complete_proofs_hold_every_key() {
+        let (db, root) = trie();
+        let keys = vec![map_key(0x00), map_key(0x40), map_key(0x80)];
+        let pages = pages(&db, &root, &keys);
+        let prefix = storage_prefix("System", "Account");
+
+        assert!(pages[0].check(&root, &prefix).unwrap().is_empty());
+        assert_eq!(pages[1].check(&root, &prefix).unwrap().len(), 3);
+        assert_eq!(ProvenTrie::new(&root, &pages).unwrap().keys(&prefix).unwrap(), keys);
+    }
+
+    #[test]
+    fn missing_subtree_fails() {
+        let (db, root) = trie();
+        let pages = pages(&db, &root, &[map_key(0x00), map_key(0x80)]);
+        let trie = ProvenTrie::new(&root, &pages).unwrap();
+
+        let err = trie.keys(&storage_prefix("System", "Account")).unwrap_err();
+        assert!(err.to_string().contains("do not cover all keys"));
+    }
+
+    #[test]
+    fn absent_key_fails() {
+        let (db, root) = trie();
+        let absent = map_key(0x20);
+        let page = PageProof {
+            map: MAP.to_string(),
+            keys: vec![hex::encode(&absent)],
+            nodes: prove(&db, &root, &[absent]).iter().map(hex::encode).collect(),
+        };
+
+        let err = page.check(&root, &storage_prefix("System", "Account")).unwrap_err();
+        assert!(err.to_string().contains("to be absent"));
+    }
+
+    /// A page may not pass off a key of another map as one of its own, e.g. to
+    /// make up for an account left out of every page.
+    #[test]
+    fn mislabelled_key_fails() {
+        let (db, root) = trie();
+        let keys = vec![map_key(0x00), storage_prefix("Balances", "TotalIssuance")];
+        let page = PageProof {
+            map: MAP.to_string(),
+            keys: keys.iter().map(hex::encode).collect(),
+            nodes: prove(&db, &root, &keys).iter().map(hex::encode).collect(),
+        };
+
+        let err = page.check(&root, &storage_prefix("System", "Account")).unwrap_err();
+        assert!(err.to_string().contains("of another map"));
+    }
+
+    #[test]
+    fn other_state_root_fails() {
+        let (db, root) = trie();
+        let pages = pages(&db, &root, &[map_key(0x00)]);
+
+        assert!(pages[1].check(&[0; 32], &storage_prefix("System", "Account")).is_err());
+    }
+}
@@ -0,0 +1,126 @@
+use serde::{ Serialize, Deserialize };
+use anyhow::Result;
+use sp_core::U256;
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use crate::balances::Balance;
+use crate::distribution::percent;
+
+pub const TOTALS_FILE: &str = "totals.json";
+
+/// Totals the runtime keeps itself, read at the snapshot block, along with
+/// accounts the runtime treats specially.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ChainTotals {
+    /// `Balances.TotalIssuance`, the free and reserved funds of all accounts
+    pub total_issuance: u128,
+    /// `SubspaceModule.TotalStake`, if the runtime has it
+    pub total_stake: Option<u128>,
+    /// `GovernanceModule.DaoTreasuryAddress`, if the runtime has it
+    #[serde(default)]
+    pub dao_treasury: Option<String>,
+}
+
+impl ChainTotals {
+    pub async fn save(&self, dir: &Path) -> Result<()> {
+        let json = serde_json::to_string_pretty(self)?;
+        tokio::fs::write(dir.join(TOTALS_FILE), json).await?;
+
+        Ok(())
+    }
+
+    pub async fn load(dir: &Path) -> Result<Self> {
+        let json = tokio::fs::read_to_string(dir.join(TOTALS_FILE)).await?;
+        Ok(serde_json::from_str(&json)?)
+    }
+}
+
+/// One on-chain total compared with the same figure summed over the snapshot.
+#[derive(Serialize, Debug)]
+pub struct Discrepancy {
+    pub name: &'static str,
+    pub chain: u128,
+    pub snapshot: u128,
+    /// `|chain - snapshot|`
+    pub absolute: u128,
+    /// Absolute discrepancy relative to the on-chain figure, in percent,
+    /// rounded down to 4 decimals for display
+    pub relative: f64,
+}
+
+impl Discrepancy {
+    fn new(name: &'static str, chain: u128, snapshot: u128) -> Self {
+        let absolute = chain.abs_diff(snapshot);
+        let relative = match (absolute, chain) {
+            (0, _) => 0.0,
+            (_, 0) => f64::INFINITY,
+            _ => percent(absolute, chain),
+        };
+
+        Self { name, chain, snapshot, absolute, relative }
+    }
+
+    /// Whether the discrepancy is larger than `tolerance` percent of the
+    /// on-chain figure. Compared exactly, down to 1e-10 percent, so that any
+    /// discrepancy exceeds a tolerance of 0.
+    pub fn exceeds(&self, tolerance: f64) -> bool {
+        // Tolerance in parts per 1e12 of the on-chain figure
+        let tolerance = U256::from((tolerance.max(0.0) * 1e10).round() as u128);
+        U256::from(self.absolute) * U256::from(10u128.pow(12)) > tolerance * U256::from(self.chain)
+    }
+}
+
+/// Compares the chain totals with the aggregated balances:
+/// - free and reserved funds against `TotalIssuance`, since staking takes
+///   funds out of the issuance tracked by the balances pallet;
+/// - outgoing stake against `TotalStake`;
+/// - the snapshot total against both combined.
+pub fn reconcile(totals: &ChainTotals, balances: &BTreeMap<String, Balance>) -> Vec<Discrepancy> {
+    let (mut issued, mut staked, mut total) = (0u128, 0u128, 0u128);
+    for balance in balances.values() {
+        issued += balance.free + balance.reserved;
+        staked += balance.staked_out;
+        total += balance.total;
+    }
+
+    let mut discrepancies = vec![Discrepancy::new("Balances", totals.total_issuance, issued)];
+    if let Some(total_stake) = totals.total_stake {
+        discrepancies.push(Discrepancy::new("Stake", total_stake, staked));
+        discrepancies.push(Discrepancy::new("Total", totals.total_issuance + total_stake, total));
+    }
+
+    discrepancies
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn any_discrepancy_exceeds_zero_tolerance() {
+        assert!(Discrepancy::new("total", u128::MAX, u128::MAX - 1).exceeds(0.0));
+        assert!(Discrepancy::new("total", 1000, 1001).exceeds(0.0));
+        assert!(!Discrepancy::new("total", 1000, 1000).exceeds(0.0));
+    }
+
+    #[test]
+    fn discrepancy_at_the_tolerance_does_not_exceed_it() {
+        // 0.1% of 1e6 is 1000
+        assert!(!Discrepancy::new("total", 1_000_000, 999_000).exceeds(0.1));
+        assert!(!Discrepancy::new("total", 1_000_000, 1_001_000).exceeds(0.1));
+        assert!(Discrepancy::new("total", 1_000_000, 1_001_001).exceeds(0.1));
+        // 1e-10 percent of 1e30 is 1e18
+        let chain = 10u128.pow(30);
+        assert!(!Discrepancy::new("total", chain, chain + 10u128.pow(18)).exceeds(1e-10));
+        assert!(Discrepancy::new("total", chain, chain + 10u128.pow(18) + 1).exceeds(1e-10));
+    }
+
+    #[test]
+    fn zero_on_chain_figure_does_not_panic() {
+        let discrepancy = Discrepancy::new("total", 0, 5);
+        assert_eq!(discrepancy.relative, f64::INFINITY);
+        assert!(discrepancy.exceeds(100.0));
+        assert!(!Discrepancy::new("total", 0, 0).exceeds(0.0));
+    }
+}
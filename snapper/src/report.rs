@@ -0,0 +1,272 @@
+use serde::Serialize;
+use clap::ValueEnum;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+use crate::SnapshotBlock;
+use crate::balances::Balance;
+use crate::distribution::{ Bucket, Distribution, percent };
+use crate::holdings::HoldingSource;
+use crate::profile::NetworkProfile;
+
+#[derive(ValueEnum, Clone, Copy, Debug, Default)]
+pub enum ReportFormat {
+    #[default]
+    Text,
+    Markdown,
+    Json,
+}
+
+/// Summary of a snapshot, rendered as text, Markdown or JSON.
+#[derive(Serialize, Debug)]
+pub struct Report {
+    pub block: SnapshotBlock,
+    pub accounts: usize,
+    pub stake_entries: usize,
+    pub addresses: usize,
+    pub total_issuance: u128,
+    /// Part of the total credited by each holding source
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub held: BTreeMap<HoldingSource, u128>,
+    /// Amounts each holding source found but could not credit to an address,
+    /// not part of the total
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub unattributed: BTreeMap<HoldingSource, u128>,
+    pub dust: DustSummary,
+    pub distribution: Distribution,
+    pub top: Vec<Holder>,
+}
+
+/// Addresses holding less than the existential deposit in total.
+#[derive(Serialize, Debug)]
+pub struct DustSummary {
+    pub existential_deposit: u128,
+    pub accounts: usize,
+    pub total: u128,
+}
+
+#[derive(Serialize, Debug)]
+pub struct Holder {
+    pub address: String,
+    pub total: u128,
+    /// Share of the total issuance in percent.
+    pub share: f64,
+}
+
+impl Report {
+    pub fn new(
+        balances: &BTreeMap<String, Balance>,
+        unattributed: &BTreeMap<HoldingSource, u128>,
+        accounts: usize,
+        stake_entries: usize,
+        block: SnapshotBlock,
+        profile: &NetworkProfile,
+        top: usize
+    ) -> Self {
+        let total_issuance: u128 = balances.values().map(|balance| balance.total).sum();
+        let mut held = BTreeMap::new();
+        for (source, amount) in balances.values().flat_map(|balance| &balance.held) {
+            *held.entry(*source).or_default() += amount;
+        }
+
+        // Sort the holders descending by total, ties broken by address
+        let mut sorted_balances: Vec<(&String, &Balance)> = balances
+            .iter()
+            .filter(|(_, balance)| balance.holds_funds())
+            .collect();
+        sorted_balances.sort_by(|a, b| b.1.total.cmp(&a.1.total).then(a.0.cmp(b.0)));
+
+        let dust = sorted_balances
+            .iter()
+            .filter(|(_, balance)| balance.total < profile.existential_deposit)
+            .map(|(_, balance)| balance.total)
+            .collect::<Vec<u128>>();
+
+        Self {
+            block,
+            accounts,
+            stake_entries,
+            addresses: sorted_balances.len(),
+            total_issuance,
+            held,
+            unattributed: unattributed.clone(),
+            dust: DustSummary {
+                existential_deposit: profile.existential_deposit,
+                accounts: dust.len(),
+                total: dust.iter().sum(),
+            },
+            distribution: Distribution::new(
+                sorted_balances.iter().map(|(_, balance)| balance.total),
+                profile.decimals
+            ),
+            top: sorted_balances
+                .iter()
+                .take(top)
+                .map(|(address, balance)| Holder {
+                    address: address.to_string(),
+                    total: balance.total,
+                    share: percent(balance.total, total_issuance),
+                })
+                .collect(),
+        }
+    }
+
+    pub fn render(&self, format: ReportFormat, profile: &NetworkProfile) -> String {
+        match format {
+            ReportFormat::Text => self.render_text(profile),
+            ReportFormat::Markdown => self.render_markdown(profile),
+            ReportFormat::Json => serde_json::to_string_pretty(self).expect("report serializes"),
+        }
+    }
+
+    fn render_text(&self, profile: &NetworkProfile) -> String {
+        let mut out = String::new();
+        writeln!(out, "Snapshot at block #{} ({})", self.block.number, self.block.hash).unwrap();
+        writeln!(
+            out,
+            "{} addresses from {} accounts and {} stake entries",
+            self.addresses,
+            self.accounts,
+            self.stake_entries
+        ).unwrap();
+        writeln!(out, "Total Issuance: {}", profile.bal(self.total_issuance)).unwrap();
+        for (source, amount) in &self.held {
+            writeln!(out, "Held in {}: {}", source, profile.bal(*amount)).unwrap();
+        }
+        for (source, amount) in &self.unattributed {
+            writeln!(out, "Unattributed in {}: {}", source, profile.bal(*amount)).unwrap();
+        }
+        writeln!(
+            out,
+            "{} nonexistent accounts totalling {}",
+            self.dust.accounts,
+            profile.bal(self.dust.total)
+        ).unwrap();
+        let distribution = &self.distribution;
+        writeln!(
+            out,
+            "Gini coefficient over {} holders: {:.4}",
+            distribution.holders,
+            distribution.gini
+        ).unwrap();
+        writeln!(
+            out,
+            "Nakamoto coefficient: {} holders for 33%, {} holders for 51%",
+            distribution.nakamoto_33,
+            distribution.nakamoto_51
+        ).unwrap();
+        writeln!(out, "Percentiles:").unwrap();
+        for percentile in &distribution.percentiles {
+            writeln!(out, "p{}: {}", percentile.percentile, profile.bal(percentile.balance))
+                .unwrap();
+        }
+        writeln!(out, "Holders by balance:").unwrap();
+        for bucket in &distribution.histogram {
+            writeln!(
+                out,
+                "{}: {} holders with {}",
+                bucket_label(bucket, profile),
+                bucket.holders,
+                profile.bal(bucket.total)
+            ).unwrap();
+        }
+        writeln!(out, "Top {} highest total balances:", self.top.len()).unwrap();
+        for holder in &self.top {
+            writeln!(
+                out,
+                "{}: {} ({:.4}%)",
+                holder.address,
+                profile.bal(holder.total),
+                holder.share
+            ).unwrap();
+        }
+
+        out
+    }
+
+    fn render_markdown(&self, profile: &NetworkProfile) -> String {
+        let mut out = String::new();
+        writeln!(out, "## Snapshot report\n").unwrap();
+        writeln!(
+            out,
+            "Taken at block **#{}** (`{}`).\n",
+            self.block.number,
+            self.block.hash
+        ).unwrap();
+        writeln!(out, "| | |").unwrap();
+        writeln!(out, "|---|---:|").unwrap();
+        writeln!(out, "| Addresses | {} |", self.addresses).unwrap();
+        writeln!(out, "| Accounts | {} |", self.accounts).unwrap();
+        writeln!(out, "| Stake entries | {} |", self.stake_entries).unwrap();
+        writeln!(out, "| Total issuance | {} |", profile.bal(self.total_issuance)).unwrap();
+        for (source, amount) in &self.held {
+            writeln!(out, "| Held in {} | {} |", source, profile.bal(*amount)).unwrap();
+        }
+        for (source, amount) in &self.unattributed {
+            writeln!(out, "| Unattributed in {} | {} |", source, profile.bal(*amount)).unwrap();
+        }
+        writeln!(out).unwrap();
+
+        writeln!(out, "### Dust\n").unwrap();
+        writeln!(
+            out,
+            "{} accounts below the existential deposit of {} hold {} in total.\n",
+            self.dust.accounts,
+            profile.bal(self.dust.existential_deposit),
+            profile.bal(self.dust.total)
+        ).unwrap();
+
+        let distribution = &self.distribution;
+        writeln!(out, "### Distribution\n").unwrap();
+        writeln!(out, "| | |").unwrap();
+        writeln!(out, "|---|---:|").unwrap();
+        writeln!(out, "| Holders | {} |", distribution.holders).unwrap();
+        writeln!(out, "| Gini coefficient | {:.4} |", distribution.gini).unwrap();
+        writeln!(out, "| Nakamoto coefficient (33%) | {} |", distribution.nakamoto_33).unwrap();
+        writeln!(out, "| Nakamoto coefficient (51%) | {} |", distribution.nakamoto_51).unwrap();
+        for percentile in &distribution.percentiles {
+            writeln!(
+                out,
+                "| p{} balance | {} |",
+                percentile.percentile,
+                profile.bal(percentile.balance)
+            ).unwrap();
+        }
+        writeln!(out).unwrap();
+        writeln!(out, "| Balance | Holders | Held |").unwrap();
+        writeln!(out, "|---|---:|---:|").unwrap();
+        for bucket in &distribution.histogram {
+            writeln!(
+                out,
+                "| {} | {} | {} |",
+                bucket_label(bucket, profile),
+                bucket.holders,
+                profile.bal(bucket.total)
+            ).unwrap();
+        }
+        writeln!(out).unwrap();
+
+        writeln!(out, "### Top {} holders\n", self.top.len()).unwrap();
+        writeln!(out, "| # | Address | Balance | Share |").unwrap();
+        writeln!(out, "|---:|---|---:|---:|").unwrap();
+        for (idx, holder) in self.top.iter().enumerate() {
+            writeln!(
+                out,
+                "| {} | `{}` | {} | {:.4}% |",
+                idx + 1,
+                holder.address,
+                profile.bal(holder.total),
+                holder.share
+            ).unwrap();
+        }
+
+        out
+    }
+}
+
+fn bucket_label(bucket: &Bucket, profile: &NetworkProfile) -> String {
+    match bucket.to {
+        Some(to) => format!("{} to {}", profile.bal(bucket.from), profile.bal(to)),
+        None => format!("{} and more", profile.bal(bucket.from)),
+    }
+}
@@ -0,0 +1,74 @@
+use anyhow::{ Result, bail };
+use clap::ValueEnum;
+use std::collections::{ BTreeMap, BTreeSet };
+use std::fmt::Write;
+
+use crate::balances::Balance;
+use crate::holdings::HoldingSource;
+
+/// Column names of the series CSV. Every holding source has a `held_` column,
+/// so that the amounts of a row add up to its total whichever sources were
+/// read.
+pub fn csv_header() -> String {
+    let held = HoldingSource::value_variants()
+        .iter()
+        .map(|source| format!("held_{}", source.to_string().replace('-', "_")))
+        .collect::<Vec<String>>();
+
+    format!("block,address,free,reserved,frozen,staked_out,staked_in,{},total\n", held.join(","))
+}
+
+/// Blocks `from`, `from + every`, ... up to and including `to`.
+pub fn series_blocks(from: u64, to: u64, every: u64) -> Result<Vec<u64>> {
+    if every == 0 {
+        bail!("--every must be at least 1");
+    }
+    if from > to {
+        bail!("--from #{} is after --to #{}", from, to);
+    }
+
+    Ok((from..=to).step_by(every as usize).collect())
+}
+
+/// Keeps only the given addresses, adding an empty balance for those that
+/// hold nothing at the block so that every address has a row per block. An
+/// empty filter keeps everything.
+pub fn filter_balances(
+    mut balances: BTreeMap<String, Balance>,
+    addresses: &BTreeSet<String>
+) -> BTreeMap<String, Balance> {
+    if addresses.is_empty() {
+        return balances;
+    }
+
+    addresses
+        .iter()
+        .map(|address| (address.clone(), balances.remove(address).unwrap_or_default()))
+        .collect()
+}
+
+/// One CSV row per address at `block`, amounts in the chain's base unit.
+pub fn csv_rows(block: u64, balances: &BTreeMap<String, Balance>) -> String {
+    let mut csv = String::new();
+    for (address, balance) in balances {
+        let held = HoldingSource::value_variants()
+            .iter()
+            .map(|source| balance.held.get(source).copied().unwrap_or_default().to_string())
+            .collect::<Vec<String>>();
+        writeln!(
+            csv,
+            "{},{},{},{},{},{},{},{},{}",
+            block,
+            address,
+            balance.free,
+            balance.reserved,
+            balance.frozen,
+            balance.staked_out,
+            balance.staked_in,
+            held.join(","),
+            balance.total
+        ).unwrap();
+    }
+
+    csv
+}
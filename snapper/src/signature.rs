@@ -0,0 +1,55 @@
+use serde::{ Serialize, Deserialize };
+use sp_core::{ Pair, sr25519 };
+use anyhow::{ Result, anyhow };
+use std::path::Path;
+
+use crate::profile::NetworkProfile;
+
+pub const SIGNATURES_FILE: &str = "signatures.json";
+
+/// An sr25519 signature over the manifest digest of a snapshot directory.
+///
+/// Signing goes through `sp_core`, which uses the same `substrate` signing
+/// context that telemetry-module verifies server signatures with.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ManifestSignature {
+    /// SS58 address of the signer
+    pub signer: String,
+    /// 0x-prefixed hex encoded signature
+    pub signature: String,
+}
+
+impl ManifestSignature {
+    pub fn sign(pair: &sr25519::Pair, digest: &[u8], profile: &NetworkProfile) -> Self {
+        Self {
+            signer: profile.encode_address(pair.public().0),
+            signature: format!("0x{}", hex::encode(pair.sign(digest).0)),
+        }
+    }
+
+    pub fn verify(&self, digest: &[u8]) -> Result<bool> {
+        let public = sr25519::Public::from_raw(NetworkProfile::decode_address(&self.signer)?);
+        let bytes: [u8; 64] = hex
+            ::decode(self.signature.trim_start_matches("0x"))?
+            .try_into()
+            .map_err(|_| anyhow!("Signature of {} is not 64 bytes long", self.signer))?;
+
+        Ok(sr25519::Pair::verify(&sr25519::Signature::from_raw(bytes), digest, &public))
+    }
+}
+
+/// Reads the signatures of a snapshot directory, none if it was never signed.
+pub async fn load_signatures(dir: &Path) -> Result<Vec<ManifestSignature>> {
+    match tokio::fs::read_to_string(dir.join(SIGNATURES_FILE)).await {
+        Ok(json) => Ok(serde_json::from_str(&json)?),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(Vec::new()),
+        Err(err) => Err(err.into()),
+    }
+}
+
+pub async fn save_signatures(dir: &Path, signatures: &[ManifestSignature]) -> Result<()> {
+    let json = serde_json::to_string_pretty(signatures)?;
+    tokio::fs::write(dir.join(SIGNATURES_FILE), json).await?;
+
+    Ok(())
+}
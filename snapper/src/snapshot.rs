@@ -0,0 +1,174 @@
+use serde::{ Serialize, Deserialize };
+use scale_value::{ At, Value };
+use anyhow::{ Result, anyhow };
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use crate::balances::{ Balance, load_balances, map_balances };
+use crate::holdings::Holdings;
+use crate::metadata::{ ChainMetadata, field_u128 };
+use crate::reconcile::ChainTotals;
+
+/// The block every storage read of a snapshot is pinned to.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SnapshotBlock {
+    pub number: u64,
+    pub hash: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct AccountData {
+    pub free: u128,
+    pub reserved: u128,
+    pub frozen: u128,
+    pub flags: u128,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Account {
+    pub nonce: u32,
+    pub consumers: u32,
+    pub providers: u32,
+    pub sufficients: u32,
+    pub data: AccountData,
+}
+
+impl Account {
+    /// Reads an account out of a decoded `System.Account` value. Runtimes that
+    /// split the frozen balance into `misc_frozen` and `fee_frozen` report the
+    /// larger of the two, and a missing `flags` field reads as zero.
+    pub fn from_value(value: &Value<u32>) -> Result<Self> {
+        let counter = |name: &str| -> Result<u32> {
+            let counter = field_u128(value, name)
+                .ok_or_else(|| anyhow!("Account has no {} field", name))?;
+            Ok(u32::try_from(counter)?)
+        };
+        let data = value.at("data").ok_or_else(|| anyhow!("Account has no data field"))?;
+        let balance = |name: &str| field_u128(data, name);
+
+        Ok(Self {
+            nonce: counter("nonce")?,
+            consumers: counter("consumers")?,
+            providers: counter("providers")?,
+            sufficients: counter("sufficients").unwrap_or_default(),
+            data: AccountData {
+                free: balance("free").ok_or_else(|| anyhow!("Account has no free balance"))?,
+                reserved: balance("reserved").unwrap_or_default(),
+                frozen: balance("frozen")
+                    .or_else(|| balance("misc_frozen").max(balance("fee_frozen")))
+                    .unwrap_or_default(),
+                flags: data
+                    .at("flags")
+                    .and_then(|flags| flags.at(0).or(Some(flags)))
+                    .and_then(|flags| flags.as_u128())
+                    .unwrap_or_default(),
+            },
+        })
+    }
+}
+
+/// A storage entry that could not be decoded, recorded by lenient snapshots.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct DecodeFailure {
+    pub map: String,
+    /// Hex encoded raw storage key
+    pub key: String,
+    /// Hex encoded raw storage value
+    pub value: String,
+    pub error: String,
+}
+
+/// Everything read at the snapshot block, before any aggregation or policy.
+#[derive(Debug, Clone)]
+pub struct Snapshot {
+    /// Where the state was read from, a node URL or a `file://` URL
+    pub source: String,
+    /// Every node a live snapshot was crawled from, `source` first
+    pub endpoints: Vec<String>,
+    pub block: SnapshotBlock,
+    pub spec_version: u32,
+    /// SCALE encoded runtime metadata of the block
+    pub metadata: Vec<u8>,
+    pub totals: ChainTotals,
+    /// `System.Account` entries by SS58 address
+    pub accounts: Vec<(String, Account)>,
+    /// `SubspaceModule.StakeTo` edges, `(from, to, amount)`
+    pub stake: Vec<(String, String, u128)>,
+    /// What the holding sources of the profile found
+    pub holdings: Holdings,
+    /// Entries a lenient read failed to decode
+    pub errors: Vec<DecodeFailure>,
+}
+
+impl Snapshot {
+    pub fn balances(&self) -> BTreeMap<String, Balance> {
+        map_balances(&self.accounts, &self.stake, &self.holdings.holdings)
+    }
+
+    pub fn decode_metadata(&self) -> Result<ChainMetadata> {
+        ChainMetadata::decode(&self.metadata)
+    }
+
+    /// Writes the snapshot files the manifest covers, except for the
+    /// aggregated balances.
+    pub async fn save(&self, dir: &Path) -> Result<()> {
+        let write = |name: &str, json: String| tokio::fs::write(dir.join(name), json);
+        write("block.json", serde_json::to_string_pretty(&self.block)?).await?;
+        tokio::fs::write(dir.join("metadata.scale"), &self.metadata).await?;
+        self.totals.save(dir).await?;
+        write("accounts.json", serde_json::to_string_pretty(&self.accounts)?).await?;
+        write("stake.json", serde_json::to_string_pretty(&self.stake)?).await?;
+        self.holdings.save(dir).await?;
+        write("errors.json", serde_json::to_string_pretty(&self.errors)?).await?;
+
+        Ok(())
+    }
+
+    /// Reads a snapshot saved to `dir`. The spec version comes from the
+    /// saved metadata.
+    pub async fn load(dir: &Path) -> Result<Self> {
+        let block = tokio::fs::read_to_string(dir.join("block.json")).await?;
+        let metadata = tokio::fs::read(dir.join("metadata.scale")).await?;
+
+        Ok(Self {
+            source: format!("file://{}", std::path::absolute(dir)?.display()),
+            endpoints: Vec::new(),
+            block: serde_json::from_str(&block)?,
+            spec_version: ChainMetadata::decode(&metadata)?.spec_version()?,
+            metadata,
+            totals: ChainTotals::load(dir).await?,
+            accounts: load_accounts(dir).await?,
+            stake: load_stake(dir).await?,
+            holdings: Holdings::load(dir).await?,
+            errors: load_errors(dir).await?,
+        })
+    }
+}
+
+pub async fn load_accounts(dir: &Path) -> Result<Vec<(String, Account)>> {
+    let json = tokio::fs::read_to_string(dir.join("accounts.json")).await?;
+    serde_json::from_str(&json).map_err(|err| anyhow!("Invalid accounts.json: {}", err))
+}
+
+/// Stake amounts are read as u128 straight from the JSON numbers, which
+/// serde_json keeps at arbitrary precision.
+pub async fn load_stake(dir: &Path) -> Result<Vec<(String, String, u128)>> {
+    let json = tokio::fs::read_to_string(dir.join("stake.json")).await?;
+    serde_json::from_str(&json).map_err(|err| anyhow!("Invalid stake.json: {}", err))
+}
+
+pub async fn load_errors(dir: &Path) -> Result<Vec<DecodeFailure>> {
+    let json = tokio::fs::read_to_string(dir.join("errors.json")).await?;
+    serde_json::from_str(&json).map_err(|err| anyhow!("Invalid errors.json: {}", err))
+}
+
+/// Balances of a snapshot directory, aggregated from its accounts and stake
+/// unless total_balances.json was already written.
+pub async fn snapshot_balances(dir: &Path) -> Result<BTreeMap<String, Balance>> {
+    if dir.join("total_balances.json").exists() {
+        load_balances(dir).await
+    } else {
+        let holdings = Holdings::load(dir).await?.holdings;
+        Ok(map_balances(&load_accounts(dir).await?, &load_stake(dir).await?, &holdings))
+    }
+}
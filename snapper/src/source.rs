@@ -0,0 +1,877 @@
+//! Where snapshots are read from: a live node, a raw state dump or the files
+//! of an earlier snapshot.
+use serde::de::DeserializeOwned;
+use subxt::utils::H256;
+use tokio::sync::Mutex;
+use anyhow::{ Result, anyhow };
+use std::collections::{ HashMap, VecDeque };
+use std::future::Future;
+use std::path::{ Path, PathBuf };
+use std::str::FromStr;
+
+use crate::checkpoint::{
+    Checkpoint,
+    MapCursor,
+    append_partial,
+    read_partial,
+    remove_partial,
+    restore_partial,
+};
+use crate::client::{ Client, storage_prefix };
+use crate::dump::StateDump;
+use crate::holdings::{ HoldingSource, RawEntries, collect_holdings, source_entries };
+use crate::metadata::ChainMetadata;
+use crate::profile::NetworkProfile;
+use crate::proof::{ BlockHeader, PROOFS_FILE, PageProof, root_from_hex };
+use crate::reconcile::ChainTotals;
+use crate::snapshot::{ Account, DecodeFailure, Snapshot, SnapshotBlock };
+
+/// Kinds of JSON lines files the crawl of a key range appends to before the
+/// final outputs are written.
+const ENTRIES: &str = "entries";
+const ERRORS: &str = "errors";
+const PROOFS: &str = "proofs";
+
+/// Key ranges each map is split into per connection, so that connections
+/// finishing early pick up more of the remaining work.
+const RANGES_PER_CONNECTION: usize = 4;
+
+/// Something a snapshot can be read from.
+pub trait SnapshotSource {
+    /// Reads the accounts, stake and totals at the source's block. Entries
+    /// that fail to decode abort the read, unless `lenient` is set, in which
+    /// case they are collected in the snapshot's errors.
+    fn snapshot(
+        &mut self,
+        profile: &NetworkProfile,
+        lenient: bool
+    ) -> impl Future<Output = Result<Snapshot>>;
+}
+
+/// Nodes to crawl. With several connections every map is split into key
+/// ranges that are crawled concurrently, one range per connection at a time.
+/// The connections may go to different nodes of the same chain, the block
+/// hash pins them all to the same state. The first connection resolves the
+/// block and reads the metadata and totals.
+///
+/// The checkpoint and the partial files of the crawl are kept in `dir`, along
+/// with the header and proofs when `proofs` is set, until `remove_crawl`
+/// removes them once the snapshot is saved.
+pub struct LiveSource<'a> {
+    pub clients: &'a mut [Client],
+    /// Block number or 0x-prefixed block hash, `None` for the latest
+    /// finalized block
+    pub block: Option<String>,
+    pub dir: PathBuf,
+    /// Continues the crawl saved in the checkpoint in `dir`, at its block
+    pub resume: bool,
+    /// Fetches a read proof for every page and checks it against the
+    /// block's state root
+    pub proofs: bool,
+}
+
+impl SnapshotSource for LiveSource<'_> {
+    async fn snapshot(&mut self, profile: &NetworkProfile, lenient: bool) -> Result<Snapshot> {
+        let dir = self.dir.as_path();
+        let ranges = match self.clients.len() {
+            0 => return Err(anyhow!("No node connection to take the snapshot with")),
+            1 => 1,
+            connections => (connections * RANGES_PER_CONNECTION).min(256),
+        };
+        let client = &mut self.clients[0];
+        tokio::fs::create_dir_all(dir).await?;
+        let mut checkpoint = if self.resume {
+            Checkpoint::load(dir).await?
+                .ok_or_else(|| anyhow!("No checkpoint to resume from in {}", dir.display()))?
+        } else {
+            let (hash, block) = resolve_block(client, self.block.clone()).await?;
+            let mut checkpoint = Checkpoint::new(dir, block);
+            if self.proofs {
+                let header = client.rpc
+                    .chain_get_header(Some(hash)).await?
+                    .ok_or_else(|| anyhow!("Header of block {:?} not found", hash))?;
+                let header = BlockHeader::new(&header)?;
+                header.save(dir).await?;
+                checkpoint.state_root = Some(header.state_root);
+            }
+            checkpoint
+        };
+        let block_hash = H256::from_str(&checkpoint.block.hash)?;
+        println!(
+            "Taking snapshot at block #{} ({})",
+            checkpoint.block.number,
+            checkpoint.block.hash
+        );
+        // Decode against the runtime of the snapshot block, not the latest one
+        let raw_metadata = client.fetch_metadata(block_hash).await?;
+        let metadata = ChainMetadata::decode(&raw_metadata)?;
+        let totals = fetch_totals(client, &metadata, profile, block_hash).await?;
+        let runtime_version = client.rpc.state_get_runtime_version(Some(block_hash)).await?;
+        // A resumed crawl keeps the ranges it was started with
+        let maps = CrawlMap::all(&profile.sources);
+        for map in &maps {
+            let (pallet, entry) = map.storage();
+            checkpoint.maps
+                .entry(map.name())
+                .or_insert_with(|| split_keys(&storage_prefix(pallet, entry), ranges));
+        }
+        checkpoint.save().await?;
+
+        let checkpoint = crawl(
+            self.clients,
+            checkpoint,
+            &maps,
+            block_hash,
+            lenient,
+            |map, key, value| map.decode(&metadata, profile, key, value)
+        ).await?;
+        let accounts = read_ranges(&checkpoint, CrawlMap::Accounts, ENTRIES).await?;
+        let stake = read_ranges(&checkpoint, CrawlMap::Stake, ENTRIES).await?;
+        let mut raw = RawEntries::new();
+        let mut errors: Vec<DecodeFailure> = Vec::new();
+        for map in &maps {
+            let cursors = &checkpoint.maps[&map.name()];
+            println!(
+                "{}: {} entries, {} failed to decode",
+                map.name(),
+                cursors.iter().map(|cursor| cursor.entries).sum::<u64>(),
+                cursors.iter().map(|cursor| cursor.failed).sum::<u64>()
+            );
+            errors.extend(read_ranges(&checkpoint, *map, ERRORS).await?);
+            if let CrawlMap::Raw(..) = map {
+                let pairs: Vec<(String, String)> = read_ranges(&checkpoint, *map, ENTRIES).await?;
+                let pairs = pairs
+                    .into_iter()
+                    .map(|(key, value)| Ok((hex::decode(key)?, hex::decode(value)?)))
+                    .collect::<Result<Vec<(Vec<u8>, Vec<u8>)>>>()?;
+                raw.insert(map.name(), pairs);
+            }
+        }
+        let holdings = collect_holdings(
+            &metadata,
+            profile,
+            &profile.sources,
+            &stake,
+            &raw,
+            lenient,
+            &mut errors
+        )?;
+        let mut proofs = String::new();
+        for map in &maps {
+            if let Some(state_root) = &checkpoint.state_root {
+                let (pallet, entry) = map.storage();
+                let prefix = storage_prefix(pallet, entry);
+                let state_root = root_from_hex(state_root)?;
+                let nodes = self.clients[0].fetch_read_proof(&[&prefix], block_hash).await?;
+                let proof = PageProof::prefix(&map.name(), &state_root, &prefix, nodes)?;
+                proofs.push_str(&serde_json::to_string(&proof)?);
+                proofs.push('\n');
+            }
+            for range in 0..checkpoint.maps[&map.name()].len() {
+                if checkpoint.state_root.is_some() {
+                    let path = dir.join(map.partial(range, PROOFS));
+                    proofs.push_str(&tokio::fs::read_to_string(&path).await?);
+                }
+            }
+        }
+        if checkpoint.state_root.is_some() {
+            tokio::fs::write(dir.join(PROOFS_FILE), proofs).await?;
+        }
+        let mut endpoints: Vec<String> = Vec::new();
+        for client in self.clients.iter() {
+            if !endpoints.contains(&client.url) {
+                endpoints.push(client.url.clone());
+            }
+        }
+        if !errors.is_empty() {
+            println!("{} entries failed to decode", errors.len());
+        }
+
+        Ok(Snapshot {
+            source: profile.url.clone(),
+            endpoints,
+            block: checkpoint.block,
+            spec_version: runtime_version.spec_version,
+            metadata: raw_metadata,
+            totals,
+            accounts,
+            stake,
+            holdings,
+            errors,
+        })
+    }
+}
+
+/// Removes the checkpoint and the partial files of a crawl in `dir`. Called
+/// once the snapshot is saved, so that `--resume` can redo a failed save.
+pub async fn remove_crawl(dir: &Path, sources: &[HoldingSource]) -> Result<()> {
+    let Some(checkpoint) = Checkpoint::load(dir).await? else {
+        return Ok(());
+    };
+    for map in CrawlMap::all(sources) {
+        let ranges = checkpoint.maps.get(&map.name()).map(Vec::len).unwrap_or_default();
+        for range in 0..ranges {
+            for kind in [ENTRIES, ERRORS, PROOFS] {
+                remove_partial(&dir.join(map.partial(range, kind))).await?;
+            }
+        }
+    }
+
+    Checkpoint::remove(dir).await
+}
+
+/// A raw state export and the metadata to decode it against. Without a node
+/// the block hash is unknown and is recorded as zero.
+pub struct DumpSource {
+    pub source: String,
+    pub dump: StateDump,
+    /// SCALE encoded runtime metadata
+    pub metadata: Vec<u8>,
+    pub block: SnapshotBlock,
+}
+
+impl DumpSource {
+    /// State held in memory, e.g. built by a test.
+    pub fn new(dump: StateDump, metadata: Vec<u8>, number: u64) -> Self {
+        Self {
+            source: "memory".to_string(),
+            dump,
+            metadata,
+            block: SnapshotBlock { number, hash: format!("{:?}", H256::zero()) },
+        }
+    }
+
+    pub async fn load(dump_path: &Path, metadata_path: &Path, number: u64) -> Result<Self> {
+        let dump = StateDump::load(dump_path).await?;
+        println!("Read {} pairs from {}", dump.pair_count(), dump_path.display());
+        let metadata = tokio::fs::read(metadata_path).await?;
+
+        Ok(Self {
+            source: format!("file://{}", std::path::absolute(dump_path)?.display()),
+            ..Self::new(dump, metadata, number)
+        })
+    }
+}
+
+impl SnapshotSource for DumpSource {
+    async fn snapshot(&mut self, profile: &NetworkProfile, lenient: bool) -> Result<Snapshot> {
+        let metadata = ChainMetadata::decode(&self.metadata)?;
+        let dump = &self.dump;
+        let totals = decode_totals(&metadata, profile, |pallet, entry| {
+            dump.get(&storage_prefix(pallet, entry)).map(<[u8]>::to_vec)
+        })?;
+
+        let map = |pallet: &str, entry: &str| dump.prefixed(&storage_prefix(pallet, entry));
+        let mut errors = Vec::new();
+        let accounts = decode_entries(
+            "System.Account",
+            map("System", "Account"),
+            lenient,
+            &mut errors,
+            |key, value| decode_account(&metadata, profile, key, value)
+        )?;
+        let stake = decode_entries(
+            "SubspaceModule.StakeTo",
+            map("SubspaceModule", "StakeTo"),
+            lenient,
+            &mut errors,
+            |key, value| decode_stake(&metadata, profile, key, value)
+        )?;
+        let raw = source_entries(&profile.sources)
+            .into_iter()
+            .map(|(pallet, entry)| {
+                let pairs = dump
+                    .prefixed(&storage_prefix(pallet, entry))
+                    .map(|(key, value)| (key.to_vec(), value.to_vec()))
+                    .collect();
+                (format!("{}.{}", pallet, entry), pairs)
+            })
+            .collect::<RawEntries>();
+        let holdings = collect_holdings(
+            &metadata,
+            profile,
+            &profile.sources,
+            &stake,
+            &raw,
+            lenient,
+            &mut errors
+        )?;
+        println!(
+            "Decoded {} accounts, {} stake entries and {} holdings, {} failed",
+            accounts.len(),
+            stake.len(),
+            holdings.holdings.len(),
+            errors.len()
+        );
+
+        Ok(Snapshot {
+            source: self.source.clone(),
+            endpoints: Vec::new(),
+            block: self.block.clone(),
+            spec_version: metadata.spec_version()?,
+            metadata: self.metadata.clone(),
+            totals,
+            accounts,
+            stake,
+            holdings,
+            errors,
+        })
+    }
+}
+
+/// The files of a snapshot saved earlier, e.g. a test fixture.
+pub struct FixtureSource {
+    pub dir: PathBuf,
+}
+
+impl SnapshotSource for FixtureSource {
+    async fn snapshot(&mut self, _profile: &NetworkProfile, _lenient: bool) -> Result<Snapshot> {
+        Snapshot::load(&self.dir).await
+    }
+}
+
+/// Resolves `--block` into a block hash and number. Accepts either a block
+/// number or a 0x-prefixed block hash; `None` means the latest finalized block.
+pub async fn resolve_block(
+    client: &Client,
+    block: Option<String>
+) -> Result<(H256, SnapshotBlock)> {
+    let hash = match block {
+        Some(block) if block.starts_with("0x") => H256::from_str(&block)?,
+        Some(block) => {
+            let number: u64 = block.parse()?;
+            client.rpc
+                .chain_get_block_hash(Some(number.into())).await?
+                .ok_or_else(|| anyhow!("Block #{} not found", number))?
+        }
+        None => client.api.blocks().at_latest().await?.hash(),
+    };
+    let number = client.api.blocks().at(hash).await?.number() as u64;
+
+    Ok((hash, SnapshotBlock { number, hash: format!("{:?}", hash) }))
+}
+
+/// Reads the account ids out of the key parts of a storage key.
+fn key_accounts<const N: usize>(
+    metadata: &ChainMetadata,
+    (pallet, entry): (&str, &str),
+    key: &[u8]
+) -> Result<[[u8; 32]; N]> {
+    let parts = metadata.decode_key(pallet, entry, key)?;
+    let accounts = parts
+        .iter()
+        .map(|part| part.as_slice().try_into())
+        .collect::<Result<Vec<[u8; 32]>, _>>()
+        .map_err(|_| anyhow!("{}.{} key parts are not account ids", pallet, entry))?;
+
+    accounts
+        .try_into()
+        .map_err(|_| anyhow!("{}.{} keys have {} parts, not {}", pallet, entry, parts.len(), N))
+}
+
+/// Storage maps a snapshot with the given holding sources reads, as
+/// `(pallet, entry)`.
+pub fn snapshot_maps(sources: &[HoldingSource]) -> Vec<(&'static str, &'static str)> {
+    CrawlMap::all(sources).into_iter().map(CrawlMap::storage).collect()
+}
+
+/// Storage maps a live snapshot crawls. The maps holding sources read are
+/// kept as raw key/value pairs, decoded once the crawl is done.
+#[derive(Clone, Copy, Debug)]
+enum CrawlMap {
+    Accounts,
+    Stake,
+    Raw(&'static str, &'static str),
+}
+
+impl CrawlMap {
+    /// Maps to crawl for a snapshot with the given holding sources, in the
+    /// order their entries are written.
+    fn all(sources: &[HoldingSource]) -> Vec<Self> {
+        [Self::Accounts, Self::Stake]
+            .into_iter()
+            .chain(
+                source_entries(sources)
+                    .into_iter()
+                    .map(|(pallet, entry)| Self::Raw(pallet, entry))
+            )
+            .collect()
+    }
+
+    fn storage(self) -> (&'static str, &'static str) {
+        match self {
+            Self::Accounts => ("System", "Account"),
+            Self::Stake => ("SubspaceModule", "StakeTo"),
+            Self::Raw(pallet, entry) => (pallet, entry),
+        }
+    }
+
+    /// `Pallet.Entry`, the key of the map's cursors in the checkpoint.
+    fn name(self) -> String {
+        let (pallet, entry) = self.storage();
+        format!("{}.{}", pallet, entry)
+    }
+
+    /// Partial file of the given kind for one key range of the map.
+    fn partial(self, range: usize, kind: &str) -> String {
+        let label = match self {
+            Self::Accounts => "accounts".to_string(),
+            Self::Stake => "stake".to_string(),
+            Self::Raw(..) => self.name(),
+        };
+        format!("{}.{}.{}.partial.jsonl", label, range, kind)
+    }
+
+    /// Decodes an entry into the JSON line it is saved as.
+    fn decode(
+        self,
+        metadata: &ChainMetadata,
+        profile: &NetworkProfile,
+        key: &[u8],
+        value: &[u8]
+    ) -> Result<String> {
+        Ok(match self {
+            Self::Accounts => {
+                serde_json::to_string(&decode_account(metadata, profile, key, value)?)?
+            }
+            Self::Stake => serde_json::to_string(&decode_stake(metadata, profile, key, value)?)?,
+            Self::Raw(..) => serde_json::to_string(&(hex::encode(key), hex::encode(value)))?,
+        })
+    }
+}
+
+/// Splits the keys under `prefix` into `count` ranges by the first byte after
+/// the prefix, which the hashed keys of a map spread evenly. A range holds
+/// the keys after its start up to and including its end, so a key equal to a
+/// bound falls into exactly one range.
+fn split_keys(prefix: &[u8], count: usize) -> Vec<MapCursor> {
+    let bound = |idx: usize| {
+        let first = (idx * 256 / count) as u8;
+        (idx > 0 && idx < count).then(|| hex::encode([prefix, &[first]].concat()))
+    };
+
+    (0..count)
+        .map(|idx| MapCursor { last_key: bound(idx), end: bound(idx + 1), ..Default::default() })
+        .collect()
+}
+
+/// Crawls every unfinished key range in the checkpoint, with one worker per
+/// connection taking the next range off a shared queue until none are left.
+/// Ranges only ever write their own partial files, so the order they finish
+/// in does not show in the output.
+async fn crawl(
+    clients: &mut [Client],
+    checkpoint: Checkpoint,
+    maps: &[CrawlMap],
+    block_hash: H256,
+    lenient: bool,
+    decode: impl Fn(CrawlMap, &[u8], &[u8]) -> Result<String>
+) -> Result<Checkpoint> {
+    let queue = maps
+        .iter()
+        .flat_map(|map| {
+            checkpoint.maps[&map.name()]
+                .iter()
+                .enumerate()
+                .filter(|(_, cursor)| !cursor.done)
+                .map(|(range, _)| (*map, range))
+        })
+        .collect::<VecDeque<(CrawlMap, usize)>>();
+    let (queue, checkpoint) = (std::sync::Mutex::new(queue), Mutex::new(checkpoint));
+
+    let workers = clients.iter_mut().map(|client| {
+        let (queue, checkpoint, decode) = (&queue, &checkpoint, &decode);
+        async move {
+            loop {
+                let next = queue.lock().expect("crawl queue is not poisoned").pop_front();
+                let Some((map, range)) = next else {
+                    return Ok::<_, anyhow::Error>(());
+                };
+                crawl_range(
+                    client,
+                    checkpoint,
+                    block_hash,
+                    (map, range),
+                    lenient,
+                    |key, value| decode(map, key, value)
+                ).await?;
+            }
+        }
+    });
+    futures::future::try_join_all(workers).await?;
+
+    Ok(checkpoint.into_inner())
+}
+
+/// Pages through one key range of a map at `block_hash`, appending the decoded
+/// entries to the range's partial file in the snapshot directory. Progress is
+/// saved to the checkpoint after each page and dropped connections are
+/// re-established, so an interrupted crawl picks up from the last saved key.
+///
+/// An entry that fails to decode aborts the crawl, unless `lenient` is set, in
+/// which case it is appended to the range's errors file together with its raw
+/// bytes.
+///
+/// If the checkpoint has a state root, each page is fetched with its read
+/// proof, which must match the returned values and is appended to the range's
+/// proofs file.
+async fn crawl_range(
+    client: &mut Client,
+    checkpoint: &Mutex<Checkpoint>,
+    block_hash: H256,
+    (map, range): (CrawlMap, usize),
+    lenient: bool,
+    decode: impl Fn(&[u8], &[u8]) -> Result<String>
+) -> Result<()> {
+    let name = map.name();
+    let (pallet, entry) = map.storage();
+    let prefix = storage_prefix(pallet, entry);
+    let (dir, state_root, mut cursor, ranges) = {
+        let checkpoint = checkpoint.lock().await;
+        let cursors = &checkpoint.maps[&name];
+        let state_root = checkpoint.state_root.as_deref().map(root_from_hex).transpose()?;
+        (checkpoint.dir.clone(), state_root, cursors[range].clone(), cursors.len())
+    };
+    let label = match ranges {
+        1 => name.clone(),
+        _ => format!("{} range {}/{}", name, range + 1, ranges),
+    };
+    let partial = dir.join(map.partial(range, ENTRIES));
+    let errors = dir.join(map.partial(range, ERRORS));
+    let proofs = dir.join(map.partial(range, PROOFS));
+    restore_partial(&partial, cursor.entries).await?;
+    restore_partial(&errors, cursor.failed).await?;
+    if state_root.is_some() {
+        restore_partial(&proofs, cursor.proofs).await?;
+    }
+    let end = cursor.end.as_deref().map(hex::decode).transpose()?;
+
+    let mut failures = 0;
+    while !cursor.done {
+        let start_key = cursor.last_key.as_deref().map(hex::decode).transpose()?;
+        let fetched = async {
+            let mut keys = client.fetch_keys(&prefix, start_key.as_deref(), block_hash).await?;
+            // Keys after the end of the range belong to the next one
+            let past_end = end.as_ref().and_then(|end| keys.iter().position(|key| key > end));
+            if let Some(idx) = past_end {
+                keys.truncate(idx);
+            }
+            let page = client.fetch_values(keys, block_hash).await?;
+            let nodes = match state_root {
+                Some(_) if !page.is_empty() => {
+                    let keys = page.iter().map(|(key, _)| key.as_slice()).collect::<Vec<_>>();
+                    Some(client.fetch_read_proof(&keys, block_hash).await?)
+                }
+                _ => None,
+            };
+            Ok::<_, anyhow::Error>((page, nodes, past_end.is_some()))
+        }.await;
+        let (page, nodes, past_end) = match fetched {
+            Ok(fetched) => {
+                failures = 0;
+                fetched
+            }
+            Err(err) => {
+                failures += 1;
+                if failures > client.retries {
+                    let context = format!(
+                        "Giving up on {} after {} retries",
+                        label,
+                        client.retries
+                    );
+                    return Err(err.context(context));
+                }
+                println!("Failed to fetch {} page: {}", label, err);
+                if let Err(err) = client.reconnect(failures).await {
+                    println!("Reconnect failed: {}", err);
+                }
+                continue;
+            }
+        };
+
+        if let Some((last_key, _)) = page.last() {
+            let proof = match (&state_root, nodes) {
+                (Some(state_root), Some(nodes)) => {
+                    Some(PageProof::new(&name, state_root, &page, nodes)?)
+                }
+                _ => None,
+            };
+            let mut lines = String::new();
+            let mut error_lines = String::new();
+            for (key, value) in &page {
+                match decode(key, value) {
+                    Ok(line) => {
+                        lines.push_str(&line);
+                        lines.push('\n');
+                        cursor.entries += 1;
+                    }
+                    Err(err) if lenient => {
+                        println!("Failed to decode {} entry 0x{}: {}", name, hex::encode(key), err);
+                        let failure = DecodeFailure {
+                            map: name.clone(),
+                            key: hex::encode(key),
+                            value: hex::encode(value),
+                            error: err.to_string(),
+                        };
+                        error_lines.push_str(&serde_json::to_string(&failure)?);
+                        error_lines.push('\n');
+                        cursor.failed += 1;
+                    }
+                    Err(err) => {
+                        let context = format!(
+                            "Failed to decode {} entry 0x{}",
+                            name,
+                            hex::encode(key)
+                        );
+                        return Err(err.context(context));
+                    }
+                }
+            }
+            append_partial(&partial, &lines).await?;
+            append_partial(&errors, &error_lines).await?;
+            if let Some(proof) = proof {
+                let line = serde_json::to_string(&proof)?;
+                append_partial(&proofs, &format!("{}\n", line)).await?;
+                cursor.proofs += 1;
+            }
+            cursor.last_key = Some(hex::encode(last_key));
+            println!("{}: {} entries", label, cursor.entries + cursor.failed);
+        }
+        if page.is_empty() || past_end {
+            cursor.done = true;
+        }
+        let mut checkpoint = checkpoint.lock().await;
+        let cursors = checkpoint.maps.get_mut(&name).expect("map was split into ranges");
+        cursors[range] = cursor.clone();
+        checkpoint.save().await?;
+    }
+
+    Ok(())
+}
+
+/// Reads the partial files of one kind of every range of `map` back, in key
+/// order.
+async fn read_ranges<T: DeserializeOwned>(
+    checkpoint: &Checkpoint,
+    map: CrawlMap,
+    kind: &str
+) -> Result<Vec<T>> {
+    let mut values = Vec::new();
+    for range in 0..checkpoint.maps[&map.name()].len() {
+        values.extend(read_partial(&checkpoint.dir.join(map.partial(range, kind))).await?);
+    }
+
+    Ok(values)
+}
+
+pub fn decode_account(
+    metadata: &ChainMetadata,
+    profile: &NetworkProfile,
+    key: &[u8],
+    value: &[u8]
+) -> Result<(String, Account)> {
+    let [account] = key_accounts(metadata, ("System", "Account"), key)?;
+    let value = metadata.decode_value("System", "Account", value)?;
+
+    Ok((profile.encode_address(account), Account::from_value(&value)?))
+}
+
+pub fn decode_stake(
+    metadata: &ChainMetadata,
+    profile: &NetworkProfile,
+    key: &[u8],
+    value: &[u8]
+) -> Result<(String, String, u128)> {
+    decode_edge(metadata, profile, ("SubspaceModule", "StakeTo"), key, value)
+}
+
+/// Decodes an entry of the `StakeFrom` mirror, which is keyed by module first,
+/// into a `(from, to, amount)` edge like `decode_stake`.
+pub fn decode_stake_from(
+    metadata: &ChainMetadata,
+    profile: &NetworkProfile,
+    key: &[u8],
+    value: &[u8]
+) -> Result<(String, String, u128)> {
+    let (to, from, staked) =
+        decode_edge(metadata, profile, ("SubspaceModule", "StakeFrom"), key, value)?;
+
+    Ok((from, to, staked))
+}
+
+/// Reads an account pair key and an amount value.
+fn decode_edge(
+    metadata: &ChainMetadata,
+    profile: &NetworkProfile,
+    (pallet, entry): (&str, &str),
+    key: &[u8],
+    value: &[u8]
+) -> Result<(String, String, u128)> {
+    let [first, second] = key_accounts(metadata, (pallet, entry), key)?;
+    let staked = metadata
+        .decode_value(pallet, entry, value)?
+        .as_u128()
+        .ok_or_else(|| anyhow!("{} value is not an unsigned integer", entry))?;
+
+    Ok((profile.encode_address(first), profile.encode_address(second), staked))
+}
+
+/// Plain storage values the chain totals are read from.
+pub const TOTALS_ENTRIES: [(&str, &str); 3] = [
+    ("Balances", "TotalIssuance"),
+    ("SubspaceModule", "TotalStake"),
+    ("GovernanceModule", "DaoTreasuryAddress"),
+];
+
+/// Reads the totals the runtime tracks itself, to reconcile the snapshot with.
+async fn fetch_totals(
+    client: &Client,
+    metadata: &ChainMetadata,
+    profile: &NetworkProfile,
+    block_hash: H256
+) -> Result<ChainTotals> {
+    let mut values = HashMap::new();
+    for (pallet, entry) in TOTALS_ENTRIES {
+        if !metadata.has_entry(pallet, entry) {
+            continue;
+        }
+        let key = storage_prefix(pallet, entry);
+        if let Some(bytes) = client.fetch_storage(&key, block_hash).await? {
+            values.insert((pallet, entry), bytes);
+        }
+    }
+
+    decode_totals(metadata, profile, |pallet, entry| values.get(&(pallet, entry)).cloned())
+}
+
+/// Decodes the chain totals out of the raw values of `TOTALS_ENTRIES`, as
+/// returned by `read`. Entries the runtime lacks or that hold nothing read as
+/// `None`.
+fn decode_totals(
+    metadata: &ChainMetadata,
+    profile: &NetworkProfile,
+    read: impl Fn(&str, &str) -> Option<Vec<u8>>
+) -> Result<ChainTotals> {
+    let read = |(pallet, entry): (&str, &str)| {
+        if metadata.has_entry(pallet, entry) { read(pallet, entry) } else { None }
+    };
+    let plain = |(pallet, entry): (&str, &str)| -> Result<Option<u128>> {
+        read((pallet, entry))
+            .map(|bytes| {
+                metadata
+                    .decode_value(pallet, entry, &bytes)?
+                    .as_u128()
+                    .ok_or_else(|| anyhow!("{}.{} is not an unsigned integer", pallet, entry))
+            })
+            .transpose()
+    };
+
+    let total_issuance = plain(("Balances", "TotalIssuance"))?
+        .ok_or_else(|| anyhow!("Balances.TotalIssuance is not set at the snapshot block"))?;
+    let total_stake = plain(("SubspaceModule", "TotalStake"))?;
+    let dao_treasury = read(("GovernanceModule", "DaoTreasuryAddress"))
+        .map(|bytes| -> Result<String> {
+            let account = <[u8; 32]>::try_from(bytes.as_slice())
+                .map_err(|_| anyhow!("DaoTreasuryAddress is not an account id"))?;
+            Ok(profile.encode_address(account))
+        })
+        .transpose()?;
+
+    Ok(ChainTotals { total_issuance, total_stake, dao_treasury })
+}
+
+/// Decodes the entries of the map called `name`, e.g. those of a state dump.
+/// Entries that fail to decode abort, unless `lenient` is set, in which case
+/// they are added to `errors` like in a live crawl.
+pub fn decode_entries<'a, T>(
+    name: &str,
+    entries: impl IntoIterator<Item = (&'a [u8], &'a [u8])>,
+    lenient: bool,
+    errors: &mut Vec<DecodeFailure>,
+    mut decode: impl FnMut(&[u8], &[u8]) -> Result<T>
+) -> Result<Vec<T>> {
+    let mut items = Vec::new();
+    for (key, value) in entries {
+        match decode(key, value) {
+            Ok(item) => items.push(item),
+            Err(err) if lenient => {
+                println!("Failed to decode {} entry: {}", name, err);
+                errors.push(DecodeFailure {
+                    map: name.to_string(),
+                    key: hex::encode(key),
+                    value: hex::encode(value),
+                    error: err.to_string(),
+                });
+            }
+            Err(err) => {
+                let context = format!("Failed to decode {} entry 0x{}", name, hex::encode(key));
+                return Err(err.context(context));
+            }
+        }
+    }
+
+    Ok(items)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PREFIX: [u8; 2] = [0xaa, 0xbb];
+
+    /// Start and end key of a range, `None` where it is unbounded
+    type Bounds = (Option<Vec<u8>>, Option<Vec<u8>>);
+
+    fn bounds(count: usize) -> Vec<Bounds> {
+        let decode = |bound: &Option<String>| {
+            bound.as_deref().map(|bound| hex::decode(bound).unwrap())
+        };
+        split_keys(&PREFIX, count)
+            .iter()
+            .map(|cursor| (decode(&cursor.last_key), decode(&cursor.end)))
+            .collect()
+    }
+
+    #[test]
+    fn one_range_is_unbounded() {
+        assert_eq!(bounds(1), vec![(None, None)]);
+    }
+
+    #[test]
+    fn ranges_end_where_the_next_starts() {
+        let key = |first: u8| Some([PREFIX.as_slice(), &[first]].concat());
+
+        assert_eq!(
+            bounds(4),
+            vec![
+                (None, key(0x40)),
+                (key(0x40), key(0x80)),
+                (key(0x80), key(0xc0)),
+                (key(0xc0), None),
+            ]
+        );
+        assert_eq!(bounds(3), vec![(None, key(85)), (key(85), key(170)), (key(170), None)]);
+    }
+
+    #[test]
+    fn every_key_falls_into_one_range() {
+        for count in [1, 2, 3, 7, 16, 100, 256] {
+            let ranges = bounds(count);
+            assert_eq!(ranges.len(), count);
+            for first in 0..=255u8 {
+                // The bound itself and the keys just after it
+                for rest in [&[][..], &[0][..], &[0xff; 32][..]] {
+                    let key = [PREFIX.as_slice(), &[first], rest].concat();
+                    let holding = ranges
+                        .iter()
+                        .filter(|(start, end)| {
+                            start.as_ref().is_none_or(|start| &key > start) &&
+                                end.as_ref().is_none_or(|end| &key <= end)
+                        })
+                        .count();
+                    assert_eq!(holding, 1, "{} ranges, key 0x{}", count, hex::encode(&key));
+                }
+            }
+        }
+    }
+}
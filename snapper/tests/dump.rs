@@ -0,0 +1,60 @@
+//! Offline snapshot of a raw chain-spec fixture, decoded against the commune
+//! metadata shipped with snapper.
+//!
+//! The fixture holds two accounts and three `StakeTo` edges between the
+//! accounts `[1; 32]`, `[2; 32]` and `[4; 32]`, the last of which only
+//! receives stake.
+use std::path::Path;
+
+use snapper::{ DumpSource, NetworkProfile, SnapshotSource };
+
+fn address(profile: &NetworkProfile, byte: u8) -> String {
+    profile.encode_address([byte; 32])
+}
+
+#[tokio::test]
+async fn snapshot_of_raw_chain_spec() {
+    let root = Path::new(env!("CARGO_MANIFEST_DIR"));
+    let mut source = DumpSource::load(
+        &root.join("tests/fixtures/chain-spec-raw.json"),
+        &root.join("metadata.commune.scale"),
+        7
+    ).await.unwrap();
+    let profile = NetworkProfile::load(None, "commune").unwrap();
+    let snapshot = source.snapshot(&profile, false).await.unwrap();
+    let (a, b, d) = (address(&profile, 1), address(&profile, 2), address(&profile, 4));
+
+    assert_eq!(snapshot.block.number, 7);
+    assert_eq!(snapshot.totals.total_issuance, 3100);
+    assert_eq!(snapshot.totals.total_stake, Some(1500));
+    assert!(snapshot.errors.is_empty());
+
+    let accounts = snapshot.accounts
+        .iter()
+        .map(|(address, account)| {
+            let data = &account.data;
+            (address.clone(), (data.free, data.reserved, data.frozen))
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(accounts.len(), 2);
+    assert!(accounts.contains(&(a.clone(), (1000, 100, 50))));
+    assert!(accounts.contains(&(b.clone(), (2000, 0, 0))));
+
+    let mut stake = snapshot.stake.clone();
+    stake.sort();
+    let mut expected = vec![
+        (a.clone(), a.clone(), 500),
+        (a.clone(), d.clone(), 300),
+        (b.clone(), d.clone(), 700),
+    ];
+    expected.sort();
+    assert_eq!(stake, expected);
+
+    let balances = snapshot.balances();
+    assert_eq!(balances.len(), 3);
+    assert_eq!((balances[&a].staked_out, balances[&a].staked_in), (800, 500));
+    assert_eq!(balances[&a].total, 1000 + 100 + 800);
+    assert_eq!((balances[&b].staked_out, balances[&b].total), (700, 2700));
+    assert_eq!((balances[&d].staked_in, balances[&d].total), (1000, 0));
+    assert!(!balances[&d].holds_funds());
+}
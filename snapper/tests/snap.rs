@@ -0,0 +1,61 @@
+//! `snap` of the raw chain-spec fixture into the working directory, the
+//! default `--out`.
+use std::path::{ Path, PathBuf };
+use std::process::{ Command, Output };
+
+fn fixture(name: &str) -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join(name)
+}
+
+/// An empty working directory named after the test.
+fn work_dir(test: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("snapper-{}-{}", test, std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+fn snap(dir: &Path, args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_snapper"))
+        .current_dir(dir)
+        .arg("snap")
+        .arg("--dump")
+        .arg(fixture("tests/fixtures/chain-spec-raw.json"))
+        .arg("--metadata")
+        .arg(fixture("metadata.commune.scale"))
+        .args(args)
+        .output()
+        .unwrap()
+}
+
+#[test]
+fn snap_into_working_directory_keeps_policy() {
+    let dir = work_dir("policy");
+    let policy = "{ \"exclude\": [] }";
+    std::fs::write(dir.join("policy.json"), policy).unwrap();
+
+    // The second run finds the policy listed in the manifest of the first
+    for _ in 0..2 {
+        let output = snap(&dir, &["--policy", "policy.json"]);
+        assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+        assert_eq!(std::fs::read_to_string(dir.join("policy.json")).unwrap(), policy);
+        assert!(dir.join("policy_audit.json").exists());
+        assert!(dir.join("manifest.json").exists());
+    }
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn snap_into_working_directory_leaves_other_files() {
+    let dir = work_dir("other");
+    std::fs::write(dir.join("header.json"), "{}").unwrap();
+
+    let output = snap(&dir, &[]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("header.json"));
+    assert_eq!(std::fs::read_to_string(dir.join("header.json")).unwrap(), "{}");
+    assert!(!dir.join("manifest.json").exists());
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}